@@ -0,0 +1,22 @@
+extern crate markovr;
+
+use markovr::text::{TextGenerator, Tokenizer};
+
+pub fn main() {
+    // Create a new, second-order generator that works in words.
+    let mut g = TextGenerator::new(Tokenizer::Word, 2);
+
+    g.train(
+        "The quick brown fox jumps over the lazy dog. \
+         The lazy dog sleeps in the sun. \
+         A quick nap in the sun is the best part of the day! \
+         Does the brown fox ever sleep? \
+         The fox sleeps when the dog jumps over the moon.",
+    );
+
+    for _ in 0..5 {
+        if let Some(sentence) = g.generate_sentence() {
+            println!("{}", sentence);
+        }
+    }
+}
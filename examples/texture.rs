@@ -0,0 +1,34 @@
+extern crate markovr;
+
+use markovr::grid::{GenerateOptions, Policy};
+use markovr::pnm;
+use markovr::texture::OverlappingModel;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+// Usage: cargo run --example texture -- <input.ppm> <output.ppm> [width] [height]
+pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: texture <input.ppm> <output.ppm> [width] [height]");
+        std::process::exit(1);
+    }
+    let size = |i: usize| args.get(i).map_or(48, |a| a.parse().expect("bad size"));
+
+    let input = File::open(&args[1]).expect("can't open input");
+    let source = pnm::read_ppm(BufReader::new(input)).expect("can't read input");
+
+    // Learn every 3x3 window of the source image.
+    let mut m = OverlappingModel::new(3);
+    m.train(&source);
+
+    // Back out of dead ends instead of starting over, since
+    // images have many more cells than tilemaps.
+    let options = GenerateOptions::new().policy(Policy::Backtrack(1_000_000));
+    let image = m
+        .generate(size(3), size(4), &options)
+        .expect("can't generate");
+
+    let output = File::create(&args[2]).expect("can't create output");
+    pnm::write_ppm(BufWriter::new(output), &image).expect("can't write output");
+}
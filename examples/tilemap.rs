@@ -1,10 +1,12 @@
 extern crate markovr;
 
 pub fn main() {
-    // Create a new, fourth-order Markov Chain.
-    // We'll keep track of each orthogonal neighbor,
-    // and allow for any one of them to be unknown.
-    let mut m = markovr::MarkovChain::<char>::new(4, &[0, 1, 2, 3]);
+    use markovr::grid::{GenerateOptions, Grid, GridModel, Neighborhood, Policy, Symmetry};
+
+    // Look at each orthogonal neighbor, any of which may be
+    // unknown while generating. Cells outside the map count
+    // as spaces, which prevents dangling lines in the output.
+    let mut m = GridModel::new(Neighborhood::VonNeumann).border(' ');
 
     let train: Vec<Vec<char>> = "           
  ┏━━━┓     
@@ -20,77 +22,58 @@ pub fn main() {
     .map(|c| c.chars().take(12).collect())
     .collect();
 
-    // Train the model.
-    for r in 1..(train.len() - 1) {
-        let ref row = train[r];
-        for c in 1..(row.len() - 1) {
-            // Build up a view of the neighbors.
-            let neighbors = &[
-                train[r - 1][c],
-                train[r][c - 1],
-                train[r][c + 1],
-                train[r + 1][c],
-            ];
-            m.train(neighbors, train[r][c], 1);
-        }
-    }
-
-    // Generate values from the model.
-    const DIM: usize = 16;
-    let mut map: [[Option<char>; DIM]; DIM];
-    'gen: loop {
-        map = [[None; DIM]; DIM];
-        // Fill in spaces around the border. This isn't necessary,
-        // but should prevent dangling lines in the output.
-        for i in 0..DIM {
-            map[i][0] = Some(' ');
-            map[i][DIM - 1] = Some(' ');
-            map[0][i] = Some(' ');
-            map[DIM - 1][i] = Some(' ');
-        }
-        // Iterate on all non-None spaces and fill them in.
-        for r in 1..(DIM - 1) {
-            for c in 1..(DIM - 1) {
-                let neighbors = &[map[r - 1][c], map[r][c - 1], map[r][c + 1], map[r + 1][c]];
+    // Train the model on the map in all four rotations and
+    // their mirror images. Each tile needs to be turned and
+    // flipped along with the map.
+    let rotate = |c: &char| match c {
+        '┏' => '┓',
+        '┓' => '┛',
+        '┛' => '┗',
+        '┗' => '┏',
+        '━' => '┃',
+        '┃' => '━',
+        '┣' => '┳',
+        '┳' => '┫',
+        '┫' => '┻',
+        '┻' => '┣',
+        c => *c,
+    };
+    let mirror = |c: &char| match c {
+        '┏' => '┓',
+        '┓' => '┏',
+        '┗' => '┛',
+        '┛' => '┗',
+        '┣' => '┫',
+        '┫' => '┣',
+        c => *c,
+    };
+    let symmetry = Symmetry::new().rotations(rotate).reflections(mirror);
+    m.train_symmetric(&Grid::from_rows(&train), &symmetry);
 
-                map[r][c] = m.generate_from_partial(neighbors);
-                match map[r][c] {
-                    Some(_) => {}
-                    // We saw a case that wasn't in our training data,
-                    // so throw it away and try again.
-                    None => {
-                        continue 'gen;
-                    }
-                }
-            }
-        }
-        break 'gen;
-    }
+    // Generate values from the model. When a cell's neighbors
+    // weren't in our training data, throw the map away and
+    // try again.
+    let options = GenerateOptions::new().policy(Policy::Retry(10_000));
+    let map = m.generate(14, 14, &options).unwrap();
 
-    for r in 1..(DIM - 1) {
-        for c in 1..(DIM - 1) {
-            match map[r][c] {
+    for row in map.rows() {
+        for cell in row {
+            match cell {
                 Some(v) => print!("{}", v),
                 None => print!("?"),
             }
         }
-        print!("\n");
+        println!();
     }
-    // Prints:
+    // Prints something like:
     /*
-           ┏━━━━┓
-           ┃    ┃
-           ┃    ┃
-      ┏━━━━┛    ┃
-      ┃         ┃
-    ┏━┛         ┃
-    ┃           ┃
-    ┃           ┃
-    ┃           ┃
-    ┣━━━━━━━━━━━┛
-    ┃
-    ┣━━━┓
-    ┃   ┃
-    ┗━━━┛
-       */
+      ┏━━━━━━━┓
+      ┃       ┃
+      ┃       ┃
+      ┣━━━━┓  ┃
+      ┃    ┃  ┃
+      ┃  ┏━┛  ┃
+      ┃  ┃    ┃
+      ┗━━┻━━━━┛
+    */
 }
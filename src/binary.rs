@@ -0,0 +1,519 @@
+//! Versioned, compact binary model files.
+//!
+//! All multi-byte fixed-width numbers are little-endian, and
+//! "varint" means an unsigned LEB128 integer.
+//!
+//! ```text
+//! magic              4 bytes, "MKVR"
+//! version            u16
+//! order              varint
+//! optional count     varint
+//! optional indices   varint each, ascending
+//! context count      varint
+//! contexts           see below
+//! checksum           u32, CRC-32 (IEEE) of every preceding byte
+//! ```
+//!
+//! Each context is written as:
+//!
+//! ```text
+//! known mask         ceil(order / 8) bytes, only present when the
+//!                    chain has optional elements. Bit i (LSB first)
+//!                    is set when element i of the context is known.
+//! known elements     one encoded element per known position
+//! side count         varint
+//! sides              encoded element, then its weight as a varint
+//! ```
+//!
+//! Elements are encoded by their BinaryElement implementation.
+//! Contexts and sides are sorted by their encoded bytes, and
+//! contexts without any weight are left out, so equal chains
+//! always produce byte-for-byte identical files.
+//! Readers reject files with a bad magic, a version newer than
+//! they understand, or a checksum that doesn't match.
+
+use std::io::{Read, Write};
+
+use super::die::WeightedDie;
+use super::{Element, MarkovChain};
+
+const MAGIC: &[u8; 4] = b"MKVR";
+
+/// The newest format version this build can read and write.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Elements that can be stored in the binary format.
+pub trait BinaryElement: Element {
+    /// Appends the encoded element to 'out'. Every element
+    /// must take at least one byte.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decodes an element from the front of 'input' and
+    /// advances past it. Returns None if the bytes are not
+    /// a valid element.
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// Errors that can happen while reading a model file.
+#[derive(Debug)]
+pub enum BinaryError {
+    /// The underlying reader failed.
+    Io(std::io::Error),
+    /// The file doesn't start with the expected magic bytes.
+    BadMagic,
+    /// The file was written by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// The file's contents don't match its checksum.
+    ChecksumMismatch,
+    /// The checksum matched, but the contents don't make sense.
+    Corrupt(&'static str),
+}
+
+impl std::fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryError::Io(e) => write!(f, "io error: {}", e),
+            BinaryError::BadMagic => write!(f, "not a markovr model file"),
+            BinaryError::UnsupportedVersion(v) => write!(
+                f,
+                "model file version {} is newer than supported version {}",
+                v, FORMAT_VERSION
+            ),
+            BinaryError::ChecksumMismatch => write!(f, "model file checksum mismatch"),
+            BinaryError::Corrupt(reason) => write!(f, "corrupt model file: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BinaryError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BinaryError {
+    fn from(e: std::io::Error) -> Self {
+        BinaryError::Io(e)
+    }
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub(crate) fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = input.split_first()?;
+        *input = rest;
+        let bits = u64::from(byte & 0x7f);
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, b| {
+        CRC_TABLE[((c ^ u32::from(*b)) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+macro_rules! binary_unsigned {
+    ($($t:ty),*) => {$(
+        impl BinaryElement for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                write_varint(out, *self as u64);
+            }
+
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                use std::convert::TryFrom;
+                <$t>::try_from(read_varint(input)?).ok()
+            }
+        }
+    )*};
+}
+
+// Signed integers are zigzag-encoded so that small
+// negative numbers stay small.
+macro_rules! binary_signed {
+    ($($t:ty),*) => {$(
+        impl BinaryElement for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                let v = *self as i64;
+                write_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+            }
+
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                use std::convert::TryFrom;
+                let v = read_varint(input)?;
+                <$t>::try_from(((v >> 1) as i64) ^ -((v & 1) as i64)).ok()
+            }
+        }
+    )*};
+}
+
+binary_unsigned!(u16, u32, u64, usize);
+binary_signed!(i16, i32, i64, isize);
+
+impl BinaryElement for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let (byte, rest) = input.split_first()?;
+        *input = rest;
+        Some(*byte)
+    }
+}
+
+impl BinaryElement for i8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        u8::decode(input).map(|b| b as i8)
+    }
+}
+
+impl BinaryElement for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl BinaryElement for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(out, u64::from(*self as u32));
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        use std::convert::TryFrom;
+        std::char::from_u32(u32::try_from(read_varint(input)?).ok()?)
+    }
+}
+
+impl<T: BinaryElement> MarkovChain<T> {
+    /// Writes this chain to 'writer' in the binary model format.
+    /// See the module documentation for the layout.
+    ///
+    /// Chains with Indexing::Wildcard are written with their
+    /// partial contexts worked out, the same as an Expanded
+    /// chain, and read back as one. That's 2^k contexts for
+    /// each full one, with k optional elements.
+    pub fn write_binary<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let mut out: Vec<u8> = vec![];
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_varint(&mut out, self.order as u64);
+
+        let optional = &self.optional_elements;
+        write_varint(&mut out, optional.len() as u64);
+        for i in optional.iter() {
+            write_varint(&mut out, *i as u64);
+        }
+
+        // Each context is encoded on its own so it can be sorted.
+        let mut contexts: Vec<Vec<u8>> = vec![];
+        for (context, die) in self.expanded_map().iter() {
+            if die.total_weight() == 0 {
+                continue;
+            }
+            let mut block = vec![];
+            if !optional.is_empty() {
+                let mut mask = vec![0u8; self.order.div_ceil(8)];
+                for (i, e) in context.iter().enumerate() {
+                    if e.is_some() {
+                        mask[i / 8] |= 1 << (i % 8);
+                    }
+                }
+                block.extend_from_slice(&mask);
+            }
+            for e in context.iter().flatten() {
+                e.encode(&mut block);
+            }
+
+            let mut sides: Vec<(Vec<u8>, u64)> = die
+                .iter()
+                .filter(|s| s.1 > 0)
+                .map(|(e, weight)| {
+                    let mut encoded = vec![];
+                    e.encode(&mut encoded);
+                    (encoded, weight)
+                })
+                .collect();
+            sides.sort_unstable();
+            write_varint(&mut block, sides.len() as u64);
+            for (e, weight) in sides {
+                block.extend_from_slice(&e);
+                write_varint(&mut block, weight);
+            }
+            contexts.push(block);
+        }
+        contexts.sort_unstable();
+
+        write_varint(&mut out, contexts.len() as u64);
+        for block in contexts {
+            out.extend_from_slice(&block);
+        }
+
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        writer.write_all(&out)
+    }
+
+    /// Reads a chain written by write_binary.
+    pub fn read_binary<R: Read>(mut reader: R) -> Result<Self, BinaryError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(BinaryError::BadMagic);
+        }
+        if bytes.len() < MAGIC.len() + 2 + 4 {
+            return Err(BinaryError::Corrupt("file is truncated"));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > FORMAT_VERSION {
+            return Err(BinaryError::UnsupportedVersion(version));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        if crc32(body) != checksum {
+            return Err(BinaryError::ChecksumMismatch);
+        }
+
+        let mut input = &body[MAGIC.len() + 2..];
+        let varint =
+            |input: &mut &[u8]| read_varint(input).ok_or(BinaryError::Corrupt("bad varint"));
+        let element =
+            |input: &mut &[u8]| T::decode(input).ok_or(BinaryError::Corrupt("bad element"));
+
+        let order = varint(&mut input)? as usize;
+        let optional_count = varint(&mut input)? as usize;
+        // Every index takes at least a byte, so the rest of the
+        // file bounds the count before anything is allocated.
+        if optional_count > order || optional_count > input.len() {
+            return Err(BinaryError::Corrupt("too many optional elements"));
+        }
+        let mut optional = Vec::with_capacity(optional_count);
+        for _ in 0..optional_count {
+            let i = varint(&mut input)? as usize;
+            if i >= order || optional.last().is_some_and(|last| *last >= i) {
+                return Err(BinaryError::Corrupt("bad optional element index"));
+            }
+            optional.push(i);
+        }
+        let mut chain = MarkovChain::new(order, &optional);
+
+        let context_count = varint(&mut input)?;
+        for _ in 0..context_count {
+            let mask_len = match optional.is_empty() {
+                true => 0,
+                false => order.div_ceil(8),
+            };
+            // Without a mask, every element is known and takes at
+            // least a byte.
+            let least = match mask_len {
+                0 => order,
+                n => n,
+            };
+            if input.len() < least {
+                return Err(BinaryError::Corrupt("file is truncated"));
+            }
+            let (mask, rest) = input.split_at(mask_len);
+            input = rest;
+
+            let mut context = Vec::with_capacity(order);
+            for i in 0..order {
+                let known = mask.is_empty() || mask[i / 8] & (1 << (i % 8)) != 0;
+                if known {
+                    context.push(Some(element(&mut input)?));
+                } else if optional.contains(&i) {
+                    context.push(None);
+                } else {
+                    return Err(BinaryError::Corrupt("unknown element is not optional"));
+                }
+            }
+
+            let mut die = WeightedDie::new();
+            for _ in 0..varint(&mut input)? {
+                let e = element(&mut input)?;
+                die.checked_add_weight(e, varint(&mut input)?)
+                    .ok_or(BinaryError::Corrupt("weights overflow"))?;
+            }
+            if chain.probability_map.contains_key(&context) {
+                return Err(BinaryError::Corrupt("duplicate context"));
+            }
+            *chain.probability_map.get_or_insert(context) = die;
+        }
+
+        if !input.is_empty() {
+            return Err(BinaryError::Corrupt("unexpected trailing bytes"));
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: BinaryElement>(m: &MarkovChain<T>) -> Vec<u8> {
+        let mut out = vec![];
+        m.write_binary(&mut out).unwrap();
+        let read = MarkovChain::read_binary(&out[..]).unwrap();
+        assert!(read == *m);
+        out
+    }
+
+    fn resign(bytes: &mut [u8]) {
+        let len = bytes.len() - 4;
+        let checksum = crc32(&bytes[..len]);
+        bytes[len..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn varints() {
+        for v in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX].iter() {
+            let mut out = vec![];
+            write_varint(&mut out, *v);
+            let mut input = &out[..];
+            assert_eq!(read_varint(&mut input), Some(*v));
+            assert!(input.is_empty());
+        }
+        assert_eq!(read_varint(&mut &[0x80u8][..]), None);
+        assert_eq!(read_varint(&mut &[0xffu8; 10][..]), None);
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn elements() {
+        let mut m = MarkovChain::new(2, &[0, 1]);
+        m.train(&['a', 'é'], '🦀', 3);
+        m.train(&['a', 'b'], 'c', 300);
+        round_trip(&m);
+
+        let mut m = MarkovChain::new(1, &[]);
+        m.train(&[-5i32], i32::MIN, 1);
+        m.train(&[i32::MAX], 0, 2);
+        round_trip(&m);
+
+        let mut m = MarkovChain::new(0, &[]);
+        m.train(&[], true, 1);
+        round_trip(&m);
+
+        let m: MarkovChain<u8> = MarkovChain::new(9, &[8]);
+        round_trip(&m);
+    }
+
+    #[test]
+    fn canonical() {
+        let mut a = MarkovChain::new(1, &[0]);
+        let mut b = MarkovChain::new(1, &[0]);
+        let samples = [('a', 'b'), ('a', 'c'), ('b', 'a'), ('c', 'a'), ('a', 'b')];
+        for (v, r) in samples.iter() {
+            a.train(&[*v], *r, 1);
+        }
+        for (v, r) in samples.iter().rev() {
+            b.train(&[*v], *r, 1);
+        }
+        assert_eq!(round_trip(&a), round_trip(&b));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let mut m = MarkovChain::new(1, &[0]);
+        m.train(&[1u8], 2, 1);
+        let good = round_trip(&m);
+        let read = |b: &[u8]| MarkovChain::<u8>::read_binary(b);
+
+        assert!(matches!(read(b"nope"), Err(BinaryError::BadMagic)));
+        assert!(matches!(
+            read(&good[..good.len() - 1]),
+            Err(BinaryError::ChecksumMismatch)
+        ));
+
+        let mut flipped = good.clone();
+        flipped[8] ^= 1;
+        assert!(matches!(read(&flipped), Err(BinaryError::ChecksumMismatch)));
+
+        let mut newer = good.clone();
+        newer[4] = 2;
+        resign(&mut newer);
+        assert!(matches!(
+            read(&newer),
+            Err(BinaryError::UnsupportedVersion(2))
+        ));
+
+        let mut trailing = good.clone();
+        trailing.insert(good.len() - 4, 0);
+        resign(&mut trailing);
+        assert!(matches!(read(&trailing), Err(BinaryError::Corrupt(_))));
+
+        // An order far longer than the file, with one context.
+        let mut huge = MAGIC.to_vec();
+        huge.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_varint(&mut huge, u64::MAX >> 8);
+        huge.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0]);
+        let long = huge.clone();
+        resign(&mut huge);
+        assert!(matches!(read(&huge), Err(BinaryError::Corrupt(_))));
+
+        // The same with optional elements, claiming more of them
+        // than there are bytes left.
+        let mut huge = long[..long.len() - 7].to_vec();
+        write_varint(&mut huge, u64::MAX >> 9);
+        huge.extend_from_slice(&[0, 0, 0, 0]);
+        resign(&mut huge);
+        assert!(matches!(read(&huge), Err(BinaryError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,431 @@
+//! Lossless compression of byte streams, driven by a
+//! VariableOrderChain.
+//!
+//! Both sides start with an empty chain and train it on each
+//! byte right after coding it, so the model adapts to the
+//! data without ever being stored. Each byte is arithmetic
+//! coded against the chain's prediction, escaping from longer
+//! contexts to shorter ones PPM-C style, and finally to a
+//! uniform model over every byte no context offered.
+//!
+//! ```text
+//! order              varint
+//! length             varint, in bytes
+//! checksum           u32, CRC-32 (IEEE) of the uncompressed bytes
+//! coded bytes        until the end of the input
+//! ```
+
+use super::binary::{crc32, read_varint, write_varint};
+use super::VariableOrderChain;
+
+/// The longest context compress will use.
+pub const MAX_ORDER: usize = 16;
+
+// Coder state is 32 bits wide, kept in u64s so that products
+// of a range and a cumulative frequency can't overflow.
+const TOP: u64 = (1 << 32) - 1;
+const HALF: u64 = 1 << 31;
+const QUARTER: u64 = 1 << 30;
+
+/// Frequencies are scaled down to keep totals below this,
+/// which keeps the coder precise.
+const MAX_TOTAL: u64 = 1 << 24;
+
+/// The escape always gets at least this share of a context's
+/// weight, so no byte codes to less than 1/256 of a bit. That
+/// caps how many bytes the coded bits can hold.
+const ESCAPE_SHARE: u64 = 256;
+
+/// How many bits the decoder may read past the end of its
+/// input before giving up. The encoder's flush covers about
+/// 32 of them.
+const MAX_OVERRUN: usize = 64;
+
+/// Errors found while decompressing.
+#[derive(Debug)]
+pub enum CodecError {
+    /// The input wasn't produced by compress, or was damaged.
+    Corrupt(&'static str),
+    /// The decompressed bytes don't match the checksum.
+    ChecksumMismatch,
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Corrupt(why) => write!(f, "corrupt compressed data: {}", why),
+            CodecError::ChecksumMismatch => write!(f, "decompressed data fails its checksum"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Frequencies of one coding step: the sides of a context,
+/// then the escape, scaled down if their total is too big.
+/// Every nonzero frequency stays nonzero.
+fn scaled(sides: &[(u8, u64)], escape: u64) -> (Vec<u64>, u64) {
+    let weight = sides.iter().map(|s| s.1).sum::<u64>();
+    let escape = escape.max(weight / ESCAPE_SHARE);
+    let total = weight + escape;
+    let mut shift = 0;
+    while total >> shift >= MAX_TOTAL {
+        shift += 1;
+    }
+    let scale = |w: u64| match w {
+        0 => 0,
+        _ => (w >> shift).max(1),
+    };
+    (sides.iter().map(|s| scale(s.1)).collect(), scale(escape))
+}
+
+/// Returns the cumulative range of symbol 'i' out of 'freqs'.
+fn interval(freqs: &[u64], i: usize) -> (u64, u64) {
+    let low: u64 = freqs[..i].iter().sum();
+    (low, low + freqs[i])
+}
+
+struct Encoder {
+    low: u64,
+    high: u64,
+    pending: u64,
+    out: Vec<u8>,
+    bits: u8,
+    bit_count: u8,
+}
+
+impl Encoder {
+    fn new(out: Vec<u8>) -> Self {
+        Encoder {
+            low: 0,
+            high: TOP,
+            pending: 0,
+            out,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn put_bit(&mut self, bit: bool) {
+        self.bits = (self.bits << 1) | bit as u8;
+        self.bit_count += 1;
+        if self.bit_count == 8 {
+            self.out.push(self.bits);
+            self.bits = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    /// Writes 'bit', then any bits that were waiting to find
+    /// out which half they were in.
+    fn emit(&mut self, bit: bool) {
+        self.put_bit(bit);
+        for _ in 0..self.pending {
+            self.put_bit(!bit);
+        }
+        self.pending = 0;
+    }
+
+    fn encode(&mut self, (cum_low, cum_high): (u64, u64), total: u64) {
+        let range = self.high - self.low + 1;
+        self.high = self.low + range * cum_high / total - 1;
+        self.low += range * cum_low / total;
+        loop {
+            if self.high < HALF {
+                self.emit(false);
+            } else if self.low >= HALF {
+                self.emit(true);
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.pending += 1;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+            self.low <<= 1;
+            self.high = (self.high << 1) | 1;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.pending += 1;
+        let bit = self.low >= QUARTER;
+        self.emit(bit);
+        while self.bit_count != 0 {
+            self.put_bit(false);
+        }
+        self.out
+    }
+}
+
+struct Decoder<'a> {
+    low: u64,
+    high: u64,
+    value: u64,
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8]) -> Result<Self, CodecError> {
+        let mut decoder = Decoder {
+            low: 0,
+            high: TOP,
+            value: 0,
+            input,
+            position: 0,
+        };
+        for _ in 0..32 {
+            decoder.value = (decoder.value << 1) | decoder.next_bit()?;
+        }
+        Ok(decoder)
+    }
+
+    /// Reads the next bit, or 0 past the end of the input.
+    fn next_bit(&mut self) -> Result<u64, CodecError> {
+        let bit = match self.input.get(self.position / 8) {
+            Some(byte) => (byte >> (7 - self.position % 8)) & 1,
+            None if self.position < self.input.len() * 8 + MAX_OVERRUN => 0,
+            None => return Err(CodecError::Corrupt("coded bytes end too early")),
+        };
+        self.position += 1;
+        Ok(u64::from(bit))
+    }
+
+    /// Returns where the current value falls, out of 'total'.
+    fn target(&self, total: u64) -> u64 {
+        let range = self.high - self.low + 1;
+        ((self.value - self.low + 1) * total - 1) / range
+    }
+
+    fn decode(&mut self, (cum_low, cum_high): (u64, u64), total: u64) -> Result<(), CodecError> {
+        let range = self.high - self.low + 1;
+        self.high = self.low + range * cum_high / total - 1;
+        self.low += range * cum_low / total;
+        loop {
+            if self.high < HALF {
+            } else if self.low >= HALF {
+                self.low -= HALF;
+                self.high -= HALF;
+                self.value -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+                self.value -= QUARTER;
+            } else {
+                break;
+            }
+            self.low <<= 1;
+            self.high = (self.high << 1) | 1;
+            self.value = (self.value << 1) | self.next_bit()?;
+        }
+        Ok(())
+    }
+
+    /// Finds the symbol that the current value falls in.
+    fn find(&self, freqs: &[u64], total: u64) -> usize {
+        let target = self.target(total);
+        let mut cumulative = 0;
+        for (i, f) in freqs.iter().enumerate() {
+            cumulative += f;
+            if target < cumulative {
+                return i;
+            }
+        }
+        freqs.len() - 1
+    }
+}
+
+/// Bytes that no context offered, in order.
+fn unseen(excluded: &[u8]) -> Vec<u8> {
+    (0..=255u8).filter(|b| !excluded.contains(b)).collect()
+}
+
+/// Compresses 'input', predicting each byte from up to
+/// 'order' bytes before it. Orders above MAX_ORDER are
+/// capped. Somewhere around 3 to 5 works well for text.
+pub fn compress(input: &[u8], order: usize) -> Vec<u8> {
+    let order = order.min(MAX_ORDER);
+    let mut out = vec![];
+    write_varint(&mut out, order as u64);
+    write_varint(&mut out, input.len() as u64);
+    out.extend_from_slice(&crc32(input).to_le_bytes());
+
+    let mut chain = VariableOrderChain::new(order);
+    let mut encoder = Encoder::new(out);
+    for (i, byte) in input.iter().enumerate() {
+        let view = &input[i.saturating_sub(order)..i];
+        let mut excluded = vec![];
+        let coded = chain.walk(view, true, |sides, escape| {
+            let (freqs, escape) = scaled(sides, escape);
+            let total = freqs.iter().sum::<u64>() + escape;
+            match sides.iter().position(|s| s.0 == *byte) {
+                Some(i) => {
+                    encoder.encode(interval(&freqs, i), total);
+                    Some(())
+                }
+                None => {
+                    encoder.encode((total - escape, total), total);
+                    excluded.extend(sides.iter().map(|s| s.0));
+                    None
+                }
+            }
+        });
+        if coded.is_none() {
+            let rest = unseen(&excluded);
+            let i = rest.iter().position(|b| b == byte).unwrap_or(0) as u64;
+            encoder.encode((i, i + 1), rest.len() as u64);
+        }
+        chain.train(view, *byte, 1);
+    }
+    encoder.finish()
+}
+
+/// Decompresses the output of compress.
+///
+/// Every byte codes to at least 1/256 of a bit, so a length
+/// that the coded bytes can't hold is rejected up front,
+/// before anything is decoded.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, CodecError> {
+    let mut input = input;
+    let order = read_varint(&mut input).ok_or(CodecError::Corrupt("bad order"))? as usize;
+    if order > MAX_ORDER {
+        return Err(CodecError::Corrupt("order is too large"));
+    }
+    let len = read_varint(&mut input).ok_or(CodecError::Corrupt("bad length"))?;
+    if input.len() < 4 {
+        return Err(CodecError::Corrupt("missing checksum"));
+    }
+    let (checksum, input) = input.split_at(4);
+    let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let bits = input.len() as u64 * 8 + MAX_OVERRUN as u64;
+    if len > bits.saturating_mul(ESCAPE_SHARE) {
+        return Err(CodecError::Corrupt(
+            "length is too long for the coded bytes",
+        ));
+    }
+
+    let mut chain = VariableOrderChain::new(order);
+    let mut decoder = Decoder::new(input)?;
+    let mut out: Vec<u8> = vec![];
+    for i in 0..len as usize {
+        let view = &out[i.saturating_sub(order)..i];
+        let mut excluded = vec![];
+        let decoded = chain.walk(view, true, |sides, escape| {
+            let (freqs, escape) = scaled(sides, escape);
+            let total = freqs.iter().sum::<u64>() + escape;
+            let mut all = freqs.clone();
+            all.push(escape);
+            let i = decoder.find(&all, total);
+            if let Err(e) = decoder.decode(interval(&all, i), total) {
+                return Some(Err(e));
+            }
+            match sides.get(i) {
+                Some(side) => Some(Ok(side.0)),
+                None => {
+                    excluded.extend(sides.iter().map(|s| s.0));
+                    None
+                }
+            }
+        });
+        let byte = match decoded {
+            Some(result) => result?,
+            None => {
+                let rest = unseen(&excluded);
+                if rest.is_empty() {
+                    return Err(CodecError::Corrupt("escaped past every byte"));
+                }
+                let total = rest.len() as u64;
+                let i = decoder.find(&vec![1; rest.len()], total);
+                decoder.decode((i as u64, i as u64 + 1), total)?;
+                rest[i]
+            }
+        };
+        chain.train(view, byte, 1);
+        out.push(byte);
+    }
+    if crc32(&out) != checksum {
+        return Err(CodecError::ChecksumMismatch);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8], order: usize) -> Vec<u8> {
+        let compressed = compress(input, order);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+        compressed
+    }
+
+    #[test]
+    fn round_trips() {
+        // A cheap, repeatable stream of noisy bytes.
+        let mut x: u32 = 12345;
+        let noise: Vec<u8> = (0..2000)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        for order in 0..5 {
+            round_trip(b"", order);
+            round_trip(b"a", order);
+            round_trip(b"abracadabra", order);
+            round_trip(&noise, order);
+            round_trip(&[0; 3000], order);
+        }
+    }
+
+    #[test]
+    fn compresses_text() {
+        let log: String = (0..200)
+            .map(|i| format!("2020-01-01 INFO request {} served in {}ms\n", i, i % 7))
+            .collect();
+        let compressed = round_trip(log.as_bytes(), 4);
+        assert!(compressed.len() * 4 < log.len());
+    }
+
+    #[test]
+    fn scaling() {
+        let (freqs, escape) = scaled(&[(1, MAX_TOTAL * 4), (2, 3), (3, 0)], 2);
+        assert!(freqs.iter().sum::<u64>() + escape < MAX_TOTAL);
+        assert_eq!(freqs[1], 1);
+        assert_eq!(freqs[2], 0);
+        // The escape is raised to its share of the weight.
+        assert_eq!(escape, MAX_TOTAL / ESCAPE_SHARE / 2);
+        assert_eq!(scaled(&[(1, 3)], 1).1, 1);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&[99, 1]).is_err());
+
+        let text = b"the quick brown fox jumps over the lazy dog";
+        let mut compressed = compress(text, 2);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 0x10;
+        assert!(decompress(&compressed).is_err());
+
+        // Claims more bytes than were coded.
+        let mut long = compress(text, 2);
+        long[1] = 120;
+        assert!(decompress(&long).is_err());
+
+        // Claims more bytes than the coded bits could ever hold.
+        let mut huge = vec![2];
+        write_varint(&mut huge, u64::MAX >> 1);
+        huge.extend_from_slice(&compress(text, 2)[2..]);
+        assert!(matches!(
+            decompress(&huge),
+            Err(CodecError::Corrupt(
+                "length is too long for the coded bytes"
+            ))
+        ));
+    }
+}
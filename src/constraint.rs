@@ -0,0 +1,221 @@
+//! Rules about a whole grid, like "every floor tile can be
+//! reached", that a neighborhood can't see.
+//!
+//! GridModel::fill_constrained checks every constraint each
+//! time it fills in a cell, while the rest of the grid is
+//! still empty. A constraint says whether the grid so far can
+//! still be finished in a way that meets it, so generation
+//! can back out of a dead end as soon as it's made.
+
+/// A rule about a whole grid.
+pub trait Constraint<T> {
+    /// Returns false if no way of filling in the empty cells
+    /// meets the constraint. On a complete grid, returns
+    /// whether it's met. 'cells' holds a 'width' by 'height'
+    /// grid row by row, like Grid::cells.
+    ///
+    /// Returning true while any cell is empty is always
+    /// correct, but leaves nothing to back out of until the
+    /// grid is complete.
+    fn allows(&self, cells: &[Option<T>], width: usize, height: usize) -> bool;
+}
+
+/// Marks every cell that can be reached from 'start', stepping
+/// up, down, left and right through cells 'open' accepts.
+fn reachable<T>(
+    cells: &[Option<T>],
+    width: usize,
+    start: (usize, usize),
+    open: impl Fn(Option<&T>) -> bool,
+) -> Vec<bool> {
+    let height = cells.len() / width;
+    let mut seen = vec![false; cells.len()];
+    let mut stack = vec![start];
+    seen[start.1 * width + start.0] = true;
+    while let Some((x, y)) = stack.pop() {
+        let steps = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in steps {
+            if nx >= width || ny >= height {
+                continue;
+            }
+            let i = ny * width + nx;
+            if !seen[i] && open(cells[i].as_ref()) {
+                seen[i] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    seen
+}
+
+/// Requires every passable cell to be reachable from every
+/// other, stepping up, down, left and right through passable
+/// cells. Empty cells might become passable.
+pub struct Connected<F> {
+    passable: F,
+}
+
+impl<F> Connected<F> {
+    /// Creates a Connected constraint. 'passable' says which
+    /// values can be walked through, like floors and doors.
+    pub fn new(passable: F) -> Self {
+        Connected { passable }
+    }
+}
+
+impl<T, F: Fn(&T) -> bool> Constraint<T> for Connected<F> {
+    fn allows(&self, cells: &[Option<T>], width: usize, _height: usize) -> bool {
+        let passable: Vec<usize> = (0..cells.len())
+            .filter(|i| cells[*i].as_ref().is_some_and(&self.passable))
+            .collect();
+        let start = match passable.first() {
+            Some(i) => (i % width, i / width),
+            None => return true,
+        };
+        let seen = reachable(cells, width, start, |v| v.is_none_or(&self.passable));
+        passable.iter().all(|i| seen[*i])
+    }
+}
+
+/// Requires at least 'count' cells with a value.
+pub struct AtLeast<T> {
+    value: T,
+    count: usize,
+}
+
+impl<T> AtLeast<T> {
+    /// Creates an AtLeast constraint.
+    pub fn new(value: T, count: usize) -> Self {
+        AtLeast { value, count }
+    }
+}
+
+impl<T: PartialEq> Constraint<T> for AtLeast<T> {
+    fn allows(&self, cells: &[Option<T>], _width: usize, _height: usize) -> bool {
+        let possible = cells
+            .iter()
+            .filter(|v| v.as_ref().is_none_or(|v| *v == self.value))
+            .count();
+        possible >= self.count
+    }
+}
+
+/// Requires a way from one cell to another, stepping up, down,
+/// left and right through passable cells. Both ends must be
+/// passable too.
+pub struct Path<F> {
+    from: (usize, usize),
+    to: (usize, usize),
+    passable: F,
+}
+
+impl<F> Path<F> {
+    /// Creates a Path constraint between the (x, y) cells
+    /// 'from' and 'to'. It's never met if either one is
+    /// outside the grid.
+    pub fn new(from: (usize, usize), to: (usize, usize), passable: F) -> Self {
+        Path { from, to, passable }
+    }
+}
+
+impl<T, F: Fn(&T) -> bool> Constraint<T> for Path<F> {
+    fn allows(&self, cells: &[Option<T>], width: usize, height: usize) -> bool {
+        let inside = |(x, y): (usize, usize)| x < width && y < height;
+        let open = |v: Option<&T>| v.is_none_or(&self.passable);
+        if !inside(self.from) || !inside(self.to) {
+            return false;
+        }
+        if !open(cells[self.from.1 * width + self.from.0].as_ref()) {
+            return false;
+        }
+        reachable(cells, width, self.from, open)[self.to.1 * width + self.to.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::{GenerateOptions, Grid, GridError, GridModel, Neighborhood, Policy};
+    use super::super::rng::mix;
+    use super::*;
+
+    fn chars(text: &str) -> Grid<char> {
+        let rows: Vec<Vec<char>> = text.lines().map(|l| l.chars().collect()).collect();
+        Grid::from_rows(&rows)
+    }
+
+    fn allows(constraint: &dyn Constraint<char>, grid: &Grid<char>) -> bool {
+        constraint.allows(grid.cells(), grid.width(), grid.height())
+    }
+
+    fn floor(c: &char) -> bool {
+        *c == '.'
+    }
+
+    #[test]
+    fn partial_grids() {
+        let connected = Connected::new(floor);
+        let path = Path::new((0, 0), (3, 2), floor);
+        let split = chars(".#..\n.#..\n.#..");
+        assert!(!allows(&connected, &split));
+        assert!(!allows(&path, &split));
+        assert!(allows(&AtLeast::new('.', 9), &split));
+        assert!(!allows(&AtLeast::new('.', 10), &split));
+
+        // An empty cell could still join the two rooms.
+        let mut open = split.clone();
+        open.set(1, 2, None);
+        assert!(allows(&connected, &open));
+        assert!(allows(&path, &open));
+        assert!(allows(&AtLeast::new('.', 10), &open));
+
+        assert!(allows(&connected, &chars("###\n#.#\n###")));
+        assert!(!allows(&Path::new((0, 0), (9, 9), floor), &open));
+    }
+
+    #[test]
+    fn connected_dungeons() {
+        // Floors and walls at random, with rooms cut off from
+        // each other all over.
+        let mut sample = Grid::new(16, 16);
+        for i in 0..256 {
+            let tile = ['.', '#'][(mix(i) % 2) as usize];
+            sample.set(i as usize % 16, i as usize / 16, Some(tile));
+        }
+        let mut m = GridModel::new(Neighborhood::VonNeumann);
+        m.train(&sample);
+
+        let connected = Connected::new(floor);
+        let path = Path::new((0, 0), (9, 7), floor);
+        let enough = AtLeast::new('.', 40);
+        let constraints: [&dyn Constraint<char>; 3] = [&connected, &path, &enough];
+        let options = GenerateOptions::new()
+            .policy(Policy::Backtrack(100_000))
+            .seed(3);
+        let map = m
+            .generate_constrained(10, 8, &constraints, &options)
+            .unwrap();
+        assert!(map.is_complete());
+        for c in &constraints {
+            assert!(allows(*c, &map));
+        }
+        let plain = m.generate(10, 8, &options).unwrap();
+        assert!(!allows(&connected, &plain));
+
+        // No value ever placed can meet this one.
+        let water = AtLeast::new('~', 1);
+        assert_eq!(
+            m.generate_constrained(3, 3, &[&water], &options),
+            Err(GridError::Unsatisfiable)
+        );
+        let retry = options.policy(Policy::Retry(3));
+        assert_eq!(
+            m.generate_constrained(3, 3, &[&water], &retry),
+            Err(GridError::Constraint((2, 2)))
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Flat, spreadsheet-friendly import and export of transition counts.
+//!
+//! A table has one header row followed by one row per
+//! (context, outcome) pair:
+//!
+//! ```text
+//! context_0,context_1?,outcome,count
+//! a,b,c,3
+//! a,c,b,1
+//! ```
+//!
+//! There is one context column per element of the chain's order.
+//! A context column whose name ends in '?' is one of the chain's
+//! optional elements. The last two columns hold the outcome and
+//! how many times it was seen after the context.
+//!
+//! Elements are written with Display and read back with FromStr.
+//! Fields containing the delimiter, quotes or line breaks are
+//! quoted as in RFC 4180. Only fully-known contexts are written;
+//! contexts with unknown elements are rebuilt from the optional
+//! columns on import.
+
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use super::{Element, MarkovChain};
+
+/// Errors that can happen while reading a table.
+#[derive(Debug)]
+pub enum CsvError {
+    /// The underlying reader failed.
+    Io(std::io::Error),
+    /// The header row is missing or malformed.
+    BadHeader(String),
+    /// A row could not be parsed. 'line' is 1-based.
+    BadRow { line: usize, message: String },
+}
+
+impl std::fmt::Display for CsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "io error: {}", e),
+            CsvError::BadHeader(message) => write!(f, "bad header: {}", message),
+            CsvError::BadRow { line, message } => {
+                write!(f, "bad row on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CsvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CsvError {
+    fn from(e: std::io::Error) -> Self {
+        CsvError::Io(e)
+    }
+}
+
+fn escape(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits text into records of fields.
+/// Each record is paired with the line it started on.
+/// Blank lines are skipped.
+fn parse(text: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut records = vec![];
+    let mut record: Vec<String> = vec![];
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    let mut end_record = |record: &mut Vec<String>, field: &mut String, record_line: usize| {
+        record.push(std::mem::take(field));
+        if record.len() > 1 || !record[0].is_empty() {
+            records.push((record_line, std::mem::take(record)));
+        } else {
+            record.clear();
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+        } else if c == delimiter {
+            record.push(std::mem::take(&mut field));
+        } else {
+            match c {
+                '"' if field.is_empty() => quoted = true,
+                '\r' => {}
+                '\n' => {
+                    end_record(&mut record, &mut field, record_line);
+                    record_line = line;
+                }
+                _ => field.push(c),
+            }
+        }
+    }
+    if quoted {
+        return Err(CsvError::BadRow {
+            line: record_line,
+            message: "unterminated quoted field".to_string(),
+        });
+    }
+    end_record(&mut record, &mut field, record_line);
+    Ok(records)
+}
+
+impl<T: Element> MarkovChain<T> {
+    /// Writes the transition counts of this chain as a table.
+    ///
+    /// 'delimiter' separates fields; use ',' for CSV or '\t'
+    /// for TSV. Rows are sorted so that the same model always
+    /// produces the same table.
+    pub fn write_csv<W: Write>(&self, mut writer: W, delimiter: char) -> std::io::Result<()>
+    where
+        T: Display,
+    {
+        let sep = delimiter.to_string();
+        let mut header: Vec<String> = (0..self.order)
+            .map(|i| match self.optional_elements.contains(&i) {
+                true => format!("context_{}?", i),
+                false => format!("context_{}", i),
+            })
+            .collect();
+        header.push("outcome".to_string());
+        header.push("count".to_string());
+        writeln!(writer, "{}", header.join(&sep))?;
+
+        let mut rows: Vec<String> = vec![];
+        for (context, die) in self.probability_map.iter() {
+            if context.iter().any(|e| e.is_none()) {
+                continue;
+            }
+            for (outcome, count) in die.iter().filter(|s| s.1 > 0) {
+                let mut fields: Vec<String> = context
+                    .iter()
+                    .flatten()
+                    .map(|e| escape(&e.to_string(), delimiter))
+                    .collect();
+                fields.push(escape(&outcome.to_string(), delimiter));
+                fields.push(count.to_string());
+                rows.push(fields.join(&sep));
+            }
+        }
+        rows.sort();
+        for row in rows {
+            writeln!(writer, "{}", row)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a table written by write_csv, or by hand, into
+    /// a new chain. The order and optional elements of the
+    /// chain come from the header row.
+    ///
+    /// Rows repeating the same context and outcome add up.
+    pub fn read_csv<R: BufRead>(mut reader: R, delimiter: char) -> Result<Self, CsvError>
+    where
+        T: FromStr,
+    {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut records = parse(&text, delimiter)?.into_iter();
+
+        let header = match records.next() {
+            Some((_, h)) => h,
+            None => return Err(CsvError::BadHeader("table is empty".to_string())),
+        };
+        if header.len() < 2
+            || header[header.len() - 2].trim() != "outcome"
+            || header[header.len() - 1].trim() != "count"
+        {
+            return Err(CsvError::BadHeader(
+                "the last two columns must be 'outcome' and 'count'".to_string(),
+            ));
+        }
+        let order = header.len() - 2;
+        let optional_elements: Vec<usize> = (0..order)
+            .filter(|i| header[*i].trim().ends_with('?'))
+            .collect();
+        let mut chain = MarkovChain::new(order, &optional_elements);
+
+        for (line, record) in records {
+            let bad = |message: String| CsvError::BadRow { line, message };
+            if record.len() != header.len() {
+                return Err(bad(format!(
+                    "expected {} fields, found {}",
+                    header.len(),
+                    record.len()
+                )));
+            }
+            let elements: Vec<T> = record[..=order]
+                .iter()
+                .map(|f| {
+                    f.parse::<T>()
+                        .map_err(|_| bad(format!("can't parse element '{}'", f)))
+                })
+                .collect::<Result<_, _>>()?;
+            let count: u64 = record[order + 1]
+                .trim()
+                .parse()
+                .map_err(|_| bad(format!("can't parse count '{}'", record[order + 1])))?;
+            chain
+                .train_weight(&elements[..order], elements[order].clone(), count)
+                .ok_or_else(|| bad("counts add up past the largest u64".to_string()))?;
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(m: &MarkovChain<char>, delimiter: char) -> String {
+        let mut out = vec![];
+        m.write_csv(&mut out, delimiter).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn export() {
+        let mut m = MarkovChain::new(2, &[1]);
+        m.train(&['a', 'b'], 'c', 3);
+        m.train(&['a', 'c'], 'b', 1);
+        assert_eq!(
+            write(&m, ','),
+            "context_0,context_1?,outcome,count\na,b,c,3\na,c,b,1\n"
+        );
+        assert_eq!(
+            write(&m, '\t'),
+            "context_0\tcontext_1?\toutcome\tcount\na\tb\tc\t3\na\tc\tb\t1\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut m = MarkovChain::new(2, &[0]);
+        m.train(&['a', ','], '"', 3);
+        m.train(&['\t', 'c'], 'b', 1);
+        m.train(&['x', 'c'], 'b', 2);
+
+        for delimiter in [',', '\t'].iter() {
+            let table = write(&m, *delimiter);
+            let read: MarkovChain<char> =
+                MarkovChain::read_csv(table.as_bytes(), *delimiter).unwrap();
+            assert_eq!(read.order, 2);
+            assert_eq!(read.optional_elements, vec![0]);
+            assert_eq!(
+                read.probability_map.values().count(),
+                m.probability_map.values().count()
+            );
+            for (key, die) in m.probability_map.iter() {
+                for (e, w) in die.iter() {
+                    assert_eq!(read.probability(&key, &e), m.probability(&key, &e));
+                    assert!(w > 0);
+                }
+            }
+            assert_eq!(read.probability(&[None, Some('c')], &'b'), 1.0);
+        }
+    }
+
+    #[test]
+    fn hand_edited() {
+        let table = "context_0,outcome,count\r\n\r\na,b,2\na,b,2\na,\"c\",4\n";
+        let m: MarkovChain<char> = MarkovChain::read_csv(table.as_bytes(), ',').unwrap();
+        assert_eq!(m.probability(&[Some('a')], &'b'), 0.5);
+        assert_eq!(m.probability(&[Some('a')], &'c'), 0.5);
+    }
+
+    #[test]
+    fn errors() {
+        let read = |t: &str| MarkovChain::<char>::read_csv(t.as_bytes(), ',');
+        assert!(matches!(read(""), Err(CsvError::BadHeader(_))));
+        assert!(matches!(read("a,b,c\n"), Err(CsvError::BadHeader(_))));
+        assert!(matches!(
+            read("context_0,outcome,count\na,b\n"),
+            Err(CsvError::BadRow { line: 2, .. })
+        ));
+        assert!(matches!(
+            read("context_0,outcome,count\na,b,1\nab,b,1\n"),
+            Err(CsvError::BadRow { line: 3, .. })
+        ));
+        assert!(matches!(
+            read("context_0,outcome,count\na,b,-1\n"),
+            Err(CsvError::BadRow { line: 2, .. })
+        ));
+        assert!(matches!(
+            read("context_0,outcome,count\n\"a,b,1\n"),
+            Err(CsvError::BadRow { line: 2, .. })
+        ));
+        let huge = format!(
+            "context_0,outcome,count\na,b,{0}\na,c,{0}\n",
+            u64::MAX / 2 + 1
+        );
+        assert!(matches!(read(&huge), Err(CsvError::BadRow { line: 3, .. })));
+    }
+}
@@ -5,18 +5,25 @@ use std::convert::TryFrom;
 use rand::Rng;
 
 #[cfg(feature = "serializer")]
-use serde::{Deserialize, Serialize};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+#[cfg(feature = "serializer")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::collections::HashMap;
 
 use super::Element;
 
 /// This is a weighted die. You can add sides (faces),
 /// change their weights, and so on.
-#[derive(Clone, Eq, PartialEq)]
-#[cfg_attr(feature = "serializer", derive(Serialize, Deserialize))]
-#[cfg_attr(
-    feature = "serializer",
-    serde(bound = "T: Serialize, for<'t> T: Deserialize<'t>")
-)]
+///
+/// Two dies are equal when they roll the same sides with
+/// the same weights, regardless of the order the sides
+/// were added in. Sides with no weight are ignored.
+///
+/// Methods that can add a side, like modify and add_weight,
+/// take it by value. Methods that only look a side up, like
+/// get_probability, weight_of and remove, borrow it.
+#[derive(Clone)]
 pub struct WeightedDie<T: Element> {
     /// An element and its probabalistic weight,
     /// compared with its peers.
@@ -27,9 +34,212 @@ pub struct WeightedDie<T: Element> {
     running_weight: Vec<u64>,
 }
 
-impl<T: Element> std::fmt::Debug for WeightedDie<T> {
+impl<T: Element> PartialEq for WeightedDie<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let sides = |d: &Self| -> HashMap<T, u64> { d.iter().filter(|s| s.1 > 0).collect() };
+        sides(self) == sides(other)
+    }
+}
+
+impl<T: Element> Eq for WeightedDie<T> {}
+
+/// Collects the bytes a value feeds to its Hash impl, so values
+/// can be put in a fixed order without needing Ord. Integers
+/// are written little-endian, and usize and isize are widened
+/// to 64 bits, so the order doesn't depend on the platform.
+///
+/// The standard library doesn't promise that Hash impls feed
+/// the same bytes from one Rust release to the next, so the
+/// order may change with the compiler. Output sorted by one
+/// build still loads in any other.
+#[derive(Default)]
+struct HashBytes(Vec<u8>);
+
+impl std::hash::Hasher for HashBytes {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+/// Returns the key that serialized output and DOT graphs are
+/// sorted by.
+pub(crate) fn sort_key<H: std::hash::Hash>(value: &H) -> Vec<u8> {
+    let mut bytes = HashBytes::default();
+    value.hash(&mut bytes);
+    bytes.0
+}
+
+// Dies serialize as a list of (side, weight) pairs, sorted by the
+// bytes each side hashes, so that equal dies always serialize the
+// same way.
+#[cfg(feature = "serializer")]
+impl<T: Element> Serialize for WeightedDie<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.sorted_sides())
+    }
+}
+
+// Dies deserialize from that list, or, in human-readable
+// formats, from the struct of items and running weights that
+// 0.5 wrote.
+#[cfg(feature = "serializer")]
+impl<'de, T: Element> Deserialize<'de> for WeightedDie<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(DieVisitor(std::marker::PhantomData))
+        } else {
+            deserializer.deserialize_seq(DieVisitor(std::marker::PhantomData))
+        }
+    }
+}
+
+#[cfg(feature = "serializer")]
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum OldField {
+    Items,
+    RunningWeight,
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(feature = "serializer")]
+struct DieVisitor<T>(std::marker::PhantomData<T>);
+
+#[cfg(feature = "serializer")]
+impl<'de, T: Element> Visitor<'de> for DieVisitor<T> {
+    type Value = WeightedDie<T>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a list of sides and their weights")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut die = WeightedDie::new();
+        while let Some((side, weight)) = seq.next_element::<(T, u64)>()? {
+            die.checked_add_weight(side, weight)
+                .ok_or_else(|| de::Error::custom("the total weight of a die overflows"))?;
+        }
+        Ok(die)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut items: Option<Vec<T>> = None;
+        let mut running: Option<Vec<u64>> = None;
+        while let Some(key) = map.next_key::<OldField>()? {
+            match key {
+                OldField::Items => items = Some(map.next_value()?),
+                OldField::RunningWeight => running = Some(map.next_value()?),
+                OldField::Other => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        let items = items.ok_or_else(|| de::Error::missing_field("items"))?;
+        let running = running.ok_or_else(|| de::Error::missing_field("running_weight"))?;
+        if items.len() != running.len() || running.windows(2).any(|w| w[0] > w[1]) {
+            return Err(de::Error::custom("running weights don't match the items"));
+        }
+        let mut die = WeightedDie::new();
+        let mut last = 0;
+        for (side, total) in items.into_iter().zip(running) {
+            die.add_weight(side, total - last);
+            last = total;
+        }
+        Ok(die)
+    }
+}
+
+// Debug shows each side with its weight, like a map.
+impl<T: Element + std::fmt::Debug> std::fmt::Debug for WeightedDie<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.iter().filter(|s| s.1 > 0))
+            .finish()
+    }
+}
+
+// Display shows each side with its probability and weight,
+// like "a: 0.750 (3), b: 0.250 (1)".
+impl<T: Element + std::fmt::Display> std::fmt::Display for WeightedDie<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Element")
+        let total = self.total_weight() as f64;
+        for (i, (e, weight)) in self.iter().filter(|s| s.1 > 0).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {:.3} ({})", e, weight as f64 / total, weight)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Element> Default for WeightedDie<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects (side, weight) pairs into a die. Weights for
+/// the same side are added together.
+impl<T: Element> std::iter::FromIterator<(T, u64)> for WeightedDie<T> {
+    fn from_iter<I: IntoIterator<Item = (T, u64)>>(iter: I) -> Self {
+        let mut die = WeightedDie::new();
+        die.extend(iter);
+        die
+    }
+}
+
+/// Adds (side, weight) pairs to a die, as with add_weight.
+impl<T: Element> Extend<(T, u64)> for WeightedDie<T> {
+    fn extend<I: IntoIterator<Item = (T, u64)>>(&mut self, iter: I) {
+        for (elem, weight) in iter {
+            self.add_weight(elem, weight);
+        }
     }
 }
 
@@ -42,13 +252,12 @@ impl<T: Element> WeightedDie<T> {
         }
     }
 
-    fn find_first(&self, element: T) -> Option<usize> {
+    fn find_first(&self, element: &T) -> Option<usize> {
         let found_val = self
             .items
             .iter()
             .enumerate()
-            .filter(|v| *v.1 == element)
-            .next()
+            .find(|v| v.1 == element)
             .map(|v| v.0);
         found_val
     }
@@ -64,19 +273,19 @@ impl<T: Element> WeightedDie<T> {
         a
     }
 
-    fn less_lossy_divide(numerator: u64, denominator: u64) -> f32 {
+    pub(crate) fn less_lossy_divide(numerator: u64, denominator: u64) -> f32 {
         let mut n = numerator;
         let mut d = denominator;
         let gcd = Self::gcd(n, d);
-        n = n / gcd;
-        d = d / gcd;
-        return n as f32 / d as f32;
+        n /= gcd;
+        d /= gcd;
+        n as f32 / d as f32
     }
 
     /// Returns the probability of rolling the selected side.
     /// If the die does not contain the side, returns 0.
     /// This will be off since floats aren't exact sometimes.
-    pub fn get_probability(&self, element: T) -> f32 {
+    pub fn get_probability(&self, element: &T) -> f32 {
         match self.find_first(element) {
             Some(v) => {
                 // if there is some found element, then
@@ -90,6 +299,21 @@ impl<T: Element> WeightedDie<T> {
         }
     }
 
+    /// Returns the combined weight of every side.
+    pub fn total_weight(&self) -> u64 {
+        *self.running_weight.last().unwrap_or(&0)
+    }
+
+    /// Iterates over every side along with its weight,
+    /// in insertion order. Sides that modify brought down
+    /// to 0 weight are still included.
+    pub fn iter(&self) -> impl Iterator<Item = (T, u64)> + '_ {
+        self.items
+            .iter()
+            .enumerate()
+            .map(move |(i, e)| (e.clone(), self.get_item_weight(i)))
+    }
+
     fn get_item_weight(&self, idx: usize) -> u64 {
         match idx {
             0 => self.running_weight[idx],
@@ -128,7 +352,7 @@ impl<T: Element> WeightedDie<T> {
     /// If it doesn't exist, will add to the collection.
     /// Runs in O(n).
     pub fn modify(&mut self, elem: T, weight_delta: i32) {
-        let found = self.find_first(elem);
+        let found = self.find_first(&elem);
         match found {
             Some(v) => {
                 // In the collection, so modify it.
@@ -148,63 +372,229 @@ impl<T: Element> WeightedDie<T> {
         }
     }
 
+    /// Returns every side with weight, sorted by sort_key.
+    #[cfg(feature = "serializer")]
+    fn sorted_sides(&self) -> Vec<(T, u64)> {
+        let mut sides: Vec<(T, u64)> = self.iter().filter(|s| s.1 > 0).collect();
+        sides.sort_by_cached_key(|s| sort_key(&s.0));
+        sides
+    }
+
+    /// Adds 'weight' to an element, adding it to the
+    /// collection if needed. Unlike modify, this takes
+    /// the full u64 range of weights.
+    /// Runs in O(n).
+    ///
+    /// Panics if the total weight would overflow a u64.
+    /// See checked_add_weight.
+    pub fn add_weight(&mut self, elem: T, weight: u64) {
+        self.checked_add_weight(elem, weight)
+            .expect("the total weight of a die overflowed");
+    }
+
+    /// Same as add_weight, but returns None, leaving the die
+    /// as it was, if the total weight would overflow a u64.
+    /// Runs in O(n).
+    pub fn checked_add_weight(&mut self, elem: T, weight: u64) -> Option<()> {
+        if weight == 0 {
+            return Some(());
+        }
+        // Running weights never pass the total, so only the
+        // total needs checking.
+        self.total_weight().checked_add(weight)?;
+        let idx = match self.find_first(&elem) {
+            Some(v) => v,
+            None => {
+                self.items.push(elem);
+                self.running_weight.push(self.total_weight());
+                self.items.len() - 1
+            }
+        };
+        for i in idx..self.running_weight.len() {
+            self.running_weight[i] += weight;
+        }
+        Some(())
+    }
+
+    /// Returns the weight of 'element', or 0 if the die
+    /// doesn't have that side.
+    pub fn weight_of(&self, element: &T) -> u64 {
+        self.find_first(element)
+            .map(|i| self.get_item_weight(i))
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of sides, as counted by iter.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns true if the die has no sides.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Sets the weight of an element, adding it to the
+    /// collection if needed. Setting a weight of 0 removes
+    /// the side.
+    /// Runs in O(n).
+    ///
+    /// Panics if the total weight would overflow a u64.
+    /// See checked_set_weight.
+    pub fn set_weight(&mut self, elem: T, weight: u64) {
+        self.checked_set_weight(elem, weight)
+            .expect("the total weight of a die overflowed");
+    }
+
+    /// Same as set_weight, but returns None, leaving the die
+    /// as it was, if the total weight would overflow a u64.
+    /// Runs in O(n).
+    pub fn checked_set_weight(&mut self, elem: T, weight: u64) -> Option<()> {
+        if weight == 0 {
+            self.remove(&elem);
+            return Some(());
+        }
+        match self.find_first(&elem) {
+            Some(idx) => {
+                let old = self.get_item_weight(idx);
+                (self.total_weight() - old).checked_add(weight)?;
+                for w in self.running_weight[idx..].iter_mut() {
+                    *w = *w - old + weight;
+                }
+                Some(())
+            }
+            None => self.checked_add_weight(elem, weight),
+        }
+    }
+
+    /// Removes a side, returning its weight if it was there.
+    /// Runs in O(n).
+    pub fn remove(&mut self, element: &T) -> Option<u64> {
+        let idx = self.find_first(element)?;
+        let weight = self.get_item_weight(idx);
+        self.items.remove(idx);
+        self.running_weight.remove(idx);
+        for w in self.running_weight[idx..].iter_mut() {
+            *w -= weight;
+        }
+        Some(weight)
+    }
+
+    /// Rolls 'k' times, putting each side back after it is
+    /// rolled, so the same side can come up more than once.
+    ///
+    /// rolls, if supplied, is used the same way as the roll
+    /// param of roll. Only as many sides as it has values are
+    /// rolled.
+    /// Runs in O(k lg n).
+    pub fn sample_with_replacement(&self, k: usize, rolls: Option<&[u64]>) -> Vec<T> {
+        sample_rolls(k, rolls)
+            .filter_map(|roll| self.roll(roll))
+            .collect()
+    }
+
+    /// Rolls 'k' times, taking each side out after it is
+    /// rolled, so every side comes up at most once. Returns
+    /// fewer than k sides if the die runs out.
+    ///
+    /// rolls, if supplied, is used the same way as the roll
+    /// param of roll. Only as many sides as it has values are
+    /// rolled.
+    /// Runs in O(k n).
+    pub fn sample_without_replacement(&self, k: usize, rolls: Option<&[u64]>) -> Vec<T> {
+        let mut left = self.clone();
+        let mut picked = Vec::with_capacity(k.min(self.len()));
+        for roll in sample_rolls(k, rolls) {
+            match left.roll(roll) {
+                Some(side) => {
+                    left.remove(&side);
+                    picked.push(side);
+                }
+                None => break,
+            }
+        }
+        picked
+    }
+
     /// Select some element from the collection.
     /// This doesn't remove the element.
     /// roll is an optional param when you don't want
     /// to rely on a random value.
+    /// Returns a clone of the side that was rolled.
     /// Runs in O(lg n).
     pub fn roll(&self, roll: Option<u64>) -> Option<T> {
         let total_weight = *self.running_weight.last().unwrap_or(&0);
 
         // If there is nothing to roll, return nothing.
-        if self.items.len() == 0 || total_weight == 0 {
+        if self.items.is_empty() || total_weight == 0 {
             return None;
         }
 
-        // Figure out the roll value, if supplied.
-        let roll_result: u64 = match roll {
-            Some(r) => r % total_weight,
-            None => {
-                cfg_if! {
-                    if #[cfg(feature = "rand")] {
-                        let mut rng = rand::thread_rng();
-                        rng.gen_range(0, total_weight) as u64
-                    } else {
-                        panic!("'roll' param is not optional when the 'rand' feature is off.");
-                    }
-                }
-            }
-        };
-
-        // Binary search for the matching element.
-        let mut start: usize = 0;
-        let mut end: usize = self.items.len() - 1;
-        while start <= end {
-            let mid = (end + start) / 2;
-            let matched = self.running_weight[mid];
+        let roll_result = resolve_roll(total_weight, roll);
+        Some(self.items[find_side(&self.running_weight, roll_result)].clone())
+    }
+}
 
-            let mut one_less: u64 = 0;
-            if mid > 0 {
-                one_less = self.running_weight[mid - 1];
-            }
+/// The roll params for up to 'k' rolls: the values in 'rolls',
+/// if supplied, or k random rolls.
+fn sample_rolls(k: usize, rolls: Option<&[u64]>) -> Box<dyn Iterator<Item = Option<u64>> + '_> {
+    match rolls {
+        Some(r) => Box::new(r.iter().take(k).copied().map(Some)),
+        None => Box::new((0..k).map(|_| None)),
+    }
+}
 
-            if matched > roll_result {
-                if one_less <= roll_result {
-                    // lt current element, but gte than
-                    // the next smallest = we got our match.
-                    return Some(self.items[mid]);
+/// Figures out the roll value, if supplied.
+/// Otherwise, picks a random one.
+/// The result is always less than total_weight, which
+/// must not be 0.
+pub(crate) fn resolve_roll(total_weight: u64, roll: Option<u64>) -> u64 {
+    match roll {
+        Some(r) => r % total_weight,
+        None => {
+            cfg_if! {
+                if #[cfg(feature = "rand")] {
+                    let mut rng = rand::thread_rng();
+                    rng.gen_range(0, total_weight) as u64
                 } else {
-                    // further to the left
-                    end = mid - 1;
+                    panic!("'roll' param is not optional when the 'rand' feature is off.");
                 }
-            } else {
-                // further to the right
-                start = mid + 1;
             }
         }
+    }
+}
 
-        return Some(self.items[start]);
+/// Binary search for the index of the side that a resolved
+/// roll lands on, given the running weights of the sides.
+/// Runs in O(lg n).
+pub(crate) fn find_side(running_weight: &[u64], roll_result: u64) -> usize {
+    let mut start: usize = 0;
+    let mut end: usize = running_weight.len() - 1;
+    while start <= end {
+        let mid = (end + start) / 2;
+        let matched = running_weight[mid];
+
+        let mut one_less: u64 = 0;
+        if mid > 0 {
+            one_less = running_weight[mid - 1];
+        }
+
+        if matched > roll_result {
+            if one_less <= roll_result {
+                // lt current element, but gte than
+                // the next smallest = we got our match.
+                return mid;
+            } else {
+                // further to the left
+                end = mid - 1;
+            }
+        } else {
+            // further to the right
+            start = mid + 1;
+        }
     }
+
+    start
 }
 
 #[cfg(test)]
@@ -298,6 +688,13 @@ mod tests {
         assert_eq!(c.roll(Some(19)), w(1));
         assert_eq!(c.roll(Some(20)), w(2));
         assert_eq!(c.roll(Some(29)), w(1)); // rolled over
+
+        // Weights too big for the total leave the die alone.
+        assert_eq!(c.checked_set_weight(2, u64::MAX - 19), None);
+        assert_eq!(c.checked_add_weight(3, u64::MAX - 24), None);
+        assert_eq!(c.total_weight(), 25);
+        assert_eq!(c.checked_set_weight(2, u64::MAX - 21), Some(()));
+        assert_eq!(c.total_weight(), u64::MAX - 1);
     }
 
     #[test]
@@ -311,9 +708,99 @@ mod tests {
         c.modify(3, -10);
         // at this point, we have (1, 20) and (2, 5).
 
-        let tmp = c.get_probability(1);
+        let tmp = c.get_probability(&1);
         assert_eq!(tmp, 20.0 / 25.0);
-        assert_eq!(c.get_probability(2), 5.0 / 25.0);
-        assert_eq!(c.get_probability(9), 0.0);
+        assert_eq!(c.get_probability(&2), 5.0 / 25.0);
+        assert_eq!(c.get_probability(&9), 0.0);
+    }
+
+    #[test]
+    fn eq_ignores_order() {
+        let mut a = WeightedDie::new();
+        a.modify(1, 10);
+        a.modify(2, 5);
+        a.modify(3, 1);
+        a.modify(3, -1);
+
+        let mut b = WeightedDie::new();
+        b.modify(2, 5);
+        b.modify(1, 10);
+        assert_eq!(a, b);
+
+        b.modify(1, 1);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn sort_keys_are_little_endian() {
+        assert_eq!(sort_key(&0x0102u16), vec![2, 1]);
+        assert_eq!(sort_key(&'a'), vec![97, 0, 0, 0]);
+        assert_eq!(sort_key(&-2i32), vec![0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(sort_key(&1usize), sort_key(&1u64));
+    }
+
+    #[test]
+    fn formatting() {
+        let mut d = WeightedDie::new();
+        d.modify('a', 3);
+        d.modify('z', 1);
+        d.modify('z', -1);
+        d.modify('b', 1);
+        assert_eq!(format!("{:?}", d), "{'a': 3, 'b': 1}");
+        assert_eq!(format!("{}", d), "a: 0.750 (3), b: 0.250 (1)");
+        assert_eq!(format!("{}", WeightedDie::<u8>::new()), "");
+    }
+
+    #[test]
+    fn loot_table() {
+        // gold, jewel, sword and helmet.
+        let mut d: WeightedDie<char> = vec![('g', 6), ('j', 3), ('s', 1), ('g', 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(d.len(), 3);
+        assert_eq!(d.weight_of(&'g'), 8);
+        assert_eq!(d.weight_of(&'h'), 0);
+        assert_eq!(d.total_weight(), 12);
+
+        d.set_weight('j', 1);
+        d.set_weight('h', 2);
+        d.extend(vec![('s', 1)]);
+        assert_eq!(
+            d.iter().collect::<Vec<_>>(),
+            vec![('g', 8), ('j', 1), ('s', 2), ('h', 2)]
+        );
+        assert_eq!(d.roll(w(8)), Some('j'));
+        assert_eq!(d.roll(w(9)), Some('s'));
+
+        assert_eq!(d.remove(&'j'), Some(1));
+        assert_eq!(d.remove(&'j'), None);
+        d.set_weight('h', 0);
+        assert_eq!(d.iter().collect::<Vec<_>>(), vec![('g', 8), ('s', 2)]);
+        assert_eq!(d.roll(w(8)), Some('s'));
+        assert!(!d.is_empty());
+    }
+
+    #[test]
+    fn sampling() {
+        let d: WeightedDie<u8> = vec![(1, 1), (2, 2), (3, 3)].into_iter().collect();
+        assert_eq!(
+            d.sample_with_replacement(3, Some(&[5, 5, 0])),
+            vec![3, 3, 1]
+        );
+        // After 3 is taken out, 1 and 2 are left with a total weight of 3.
+        assert_eq!(d.sample_without_replacement(2, Some(&[5, 2])), vec![3, 2]);
+        assert_eq!(
+            d.sample_without_replacement(5, Some(&[0; 5])),
+            vec![1, 2, 3]
+        );
+        assert_eq!(d.sample_with_replacement(4, None).len(), 4);
+        assert_eq!(
+            WeightedDie::<u8>::new().sample_with_replacement(2, Some(&[0, 0])),
+            Vec::<u8>::new()
+        );
+
+        // Short roll lists stop early.
+        assert_eq!(d.sample_with_replacement(3, Some(&[0])), vec![1]);
+        assert_eq!(d.sample_without_replacement(3, Some(&[5, 2])), vec![3, 2]);
     }
 }
@@ -0,0 +1,219 @@
+use super::storage::ContextMap;
+use super::{Element, MarkovChain};
+use std::collections::HashMap;
+
+/// Pseudo-count added to every outcome of a context before
+/// comparing distributions. This keeps the divergences finite
+/// when an outcome (or an entire context) was only seen by
+/// one of the two chains.
+const SMOOTHING: f64 = 0.5;
+
+/// Smoothed outcome distributions of a single context,
+/// along with how much of each chain's training data
+/// landed in that context.
+struct ContextPair {
+    self_weight: f64,
+    other_weight: f64,
+    p: Vec<f64>,
+    q: Vec<f64>,
+}
+
+fn total_weight<T: Element>(map: &ContextMap<T>) -> u64 {
+    map.values().map(|d| d.total_weight()).sum()
+}
+
+impl<T: Element> MarkovChain<T> {
+    /// Lines up every context found in either chain, with
+    /// Wildcard chains expanded like Expanded ones, 2^k
+    /// contexts for each full one.
+    /// Outcomes are the union of both dies' sides, and each
+    /// distribution is smoothed with SMOOTHING. A context that
+    /// is missing from one chain ends up uniform for that chain.
+    fn context_pairs(&self, other: &MarkovChain<T>) -> Vec<ContextPair> {
+        let (mine, theirs) = (self.expanded_map(), other.expanded_map());
+        let self_total = total_weight(&mine);
+        let other_total = total_weight(&theirs);
+        let share = |weight: u64, total: u64| match total {
+            0 => 0.0,
+            _ => weight as f64 / total as f64,
+        };
+
+        let keys = mine
+            .keys()
+            .chain(theirs.keys().filter(|k| !mine.contains_key(k)));
+
+        keys.map(|key| {
+            // Weight of each outcome in this chain, then in 'other'.
+            let mut counts: HashMap<T, (u64, u64)> = HashMap::new();
+            if let Some(die) = mine.get(&key) {
+                for (e, w) in die.iter() {
+                    counts.entry(e).or_default().0 += w;
+                }
+            }
+            if let Some(die) = theirs.get(&key) {
+                for (e, w) in die.iter() {
+                    counts.entry(e).or_default().1 += w;
+                }
+            }
+
+            let smooth = |counts: Vec<u64>| -> (u64, Vec<f64>) {
+                let total: u64 = counts.iter().sum();
+                let denominator = total as f64 + SMOOTHING * counts.len() as f64;
+                let probs = counts
+                    .iter()
+                    .map(|c| (*c as f64 + SMOOTHING) / denominator)
+                    .collect();
+                (total, probs)
+            };
+
+            let (p_total, p) = smooth(counts.values().map(|c| c.0).collect());
+            let (q_total, q) = smooth(counts.values().map(|c| c.1).collect());
+            ContextPair {
+                self_weight: share(p_total, self_total),
+                other_weight: share(q_total, other_total),
+                p,
+                q,
+            }
+        })
+        .collect()
+    }
+
+    fn kl(p: &[f64], q: &[f64]) -> f64 {
+        p.iter()
+            .zip(q.iter())
+            .map(|(p, q)| p * (p / q).log2())
+            .sum()
+    }
+
+    /// Returns the Kullback-Leibler divergence, in bits, of
+    /// 'other' from this chain.
+    ///
+    /// The divergence is computed per context and averaged,
+    /// weighting each context by how often it was seen while
+    /// training this chain. Contexts and outcomes missing from
+    /// either chain are handled with additive smoothing.
+    ///
+    /// This is not symmetric; a.kl_divergence(b) will usually
+    /// differ from b.kl_divergence(a).
+    pub fn kl_divergence(&self, other: &MarkovChain<T>) -> f64 {
+        self.context_pairs(other)
+            .iter()
+            .map(|c| c.self_weight * Self::kl(&c.p, &c.q))
+            .sum()
+    }
+
+    /// Returns the Jensen-Shannon divergence, in bits, between
+    /// this chain and 'other'.
+    ///
+    /// Each context is weighted by how often it was seen across
+    /// both chains. The result is symmetric and falls in [0, 1].
+    pub fn js_divergence(&self, other: &MarkovChain<T>) -> f64 {
+        self.context_pairs(other)
+            .iter()
+            .map(|c| {
+                let m: Vec<f64> =
+                    c.p.iter()
+                        .zip(c.q.iter())
+                        .map(|(p, q)| (p + q) / 2.0)
+                        .collect();
+                let js = (Self::kl(&c.p, &m) + Self::kl(&c.q, &m)) / 2.0;
+                (c.self_weight + c.other_weight) / 2.0 * js
+            })
+            .sum()
+    }
+
+    /// Returns the total variation distance between this chain
+    /// and 'other'.
+    ///
+    /// Each context is weighted by how often it was seen across
+    /// both chains. The result is symmetric and falls in [0, 1].
+    pub fn total_variation_distance(&self, other: &MarkovChain<T>) -> f64 {
+        self.context_pairs(other)
+            .iter()
+            .map(|c| {
+                let tv: f64 = c.p.iter().zip(c.q.iter()).map(|(p, q)| (p - q).abs()).sum();
+                (c.self_weight + c.other_weight) / 2.0 * tv / 2.0
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alphabet(text: &str) -> MarkovChain<char> {
+        let mut m = MarkovChain::new(1, &[]);
+        let alpha: Vec<char> = text.chars().collect();
+        for i in 1..alpha.len() {
+            m.train(&[alpha[i - 1]], alpha[i], 1);
+        }
+        m
+    }
+
+    #[test]
+    fn identical() {
+        let a = alphabet("abcabcabd");
+        let b = alphabet("abcabcabd");
+        assert!(a.kl_divergence(&b).abs() < 1e-12);
+        assert!(a.js_divergence(&b).abs() < 1e-12);
+        assert!(a.total_variation_distance(&b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn empty() {
+        let a: MarkovChain<char> = MarkovChain::new(1, &[]);
+        let b: MarkovChain<char> = MarkovChain::new(1, &[]);
+        assert_eq!(a.kl_divergence(&b), 0.0);
+        assert_eq!(a.js_divergence(&b), 0.0);
+        assert_eq!(a.total_variation_distance(&b), 0.0);
+    }
+
+    #[test]
+    fn different() {
+        let a = alphabet("abababababab");
+        let b = alphabet("acacacacacac");
+
+        let kl = a.kl_divergence(&b);
+        let js = a.js_divergence(&b);
+        let tv = a.total_variation_distance(&b);
+        assert!(kl > 0.0 && kl.is_finite());
+        assert!(js > 0.0 && js <= 1.0);
+        assert!(tv > 0.0 && tv <= 1.0);
+
+        // js and tv are symmetric.
+        assert!((js - b.js_divergence(&a)).abs() < 1e-12);
+        assert!((tv - b.total_variation_distance(&a)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn across_indexing() {
+        let text: Vec<char> = "the rain in spain".chars().collect();
+        let mut expanded = MarkovChain::new(2, &[0]);
+        let mut wildcard = MarkovChain::new(2, &[0]);
+        wildcard.set_indexing(crate::Indexing::Wildcard);
+        for i in 2..text.len() {
+            expanded.train(&text[i - 2..i], text[i], 1);
+            wildcard.train(&text[i - 2..i], text[i], 1);
+        }
+        assert!(expanded == wildcard);
+        assert!(expanded.kl_divergence(&wildcard).abs() < 1e-12);
+        assert!(wildcard.kl_divergence(&expanded).abs() < 1e-12);
+        assert!(expanded.js_divergence(&wildcard).abs() < 1e-12);
+        assert!(expanded.total_variation_distance(&wildcard).abs() < 1e-12);
+
+        wildcard.train(&['i', 'n'], 'x', 1);
+        assert!(expanded != wildcard);
+        assert!(expanded.js_divergence(&wildcard) > 0.0);
+    }
+
+    #[test]
+    fn closer_is_smaller() {
+        let a = alphabet("aaaaaaaaab");
+        let near = alphabet("aaaaaaaabb");
+        let far = alphabet("abbbbbbbbb");
+        assert!(a.kl_divergence(&near) < a.kl_divergence(&far));
+        assert!(a.js_divergence(&near) < a.js_divergence(&far));
+        assert!(a.total_variation_distance(&near) < a.total_variation_distance(&far));
+    }
+}
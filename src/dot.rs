@@ -0,0 +1,292 @@
+//! Graphviz DOT export of a chain's transition graph.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Write;
+
+use super::die::sort_key;
+use super::{Element, MarkovChain};
+
+/// What the edges of an exported graph point at.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DotEdges {
+    /// Each edge points at the context you end up in after
+    /// producing the outcome, so walking the graph is the same
+    /// as generating a sequence. This suits chains trained on
+    /// sequences, like text.
+    NextContext,
+    /// Each edge points at a node for the outcome itself.
+    /// This suits chains whose view is not a sliding window,
+    /// like the neighbors in a tilemap.
+    Outcome,
+}
+
+/// Controls how MarkovChain::to_dot renders a chain.
+pub struct DotOptions<'a, T: Element> {
+    threshold: f32,
+    top_k: Option<usize>,
+    edges: DotEdges,
+    include_partial: bool,
+    label: Box<dyn Fn(&T) -> String + 'a>,
+}
+
+impl<'a, T: Element> DotOptions<'a, T> {
+    /// Creates new options.
+    ///
+    /// 'label' renders a single element as text. It is used
+    /// for both context nodes and outcome nodes.
+    ///
+    /// By default, every edge is rendered, edges point at the
+    /// next context, and partial contexts (those containing
+    /// None from optional elements) are skipped.
+    pub fn new<F>(label: F) -> Self
+    where
+        F: Fn(&T) -> String + 'a,
+    {
+        DotOptions {
+            threshold: 0.0,
+            top_k: None,
+            edges: DotEdges::NextContext,
+            include_partial: false,
+            label: Box::new(label),
+        }
+    }
+
+    /// Skips edges whose probability is below 'threshold'.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Keeps only the 'k' most likely edges out of each node.
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = Some(k);
+        self
+    }
+
+    /// Selects what edges point at.
+    pub fn edges(mut self, edges: DotEdges) -> Self {
+        self.edges = edges;
+        self
+    }
+
+    /// Also renders contexts that contain unknown elements.
+    pub fn include_partial(mut self, include: bool) -> Self {
+        self.include_partial = include;
+        self
+    }
+
+    fn context_label(&self, context: &[Option<T>]) -> String {
+        context
+            .iter()
+            .map(|e| match e {
+                Some(v) => (self.label)(v),
+                None => "_".to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// What a node of an exported graph stands for.
+#[derive(Hash, PartialEq, Eq)]
+enum Node<T> {
+    Context(Vec<Option<T>>),
+    Outcome(T),
+}
+
+/// Quotes a string so it can be used as a DOT ID.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl<T: Element> MarkovChain<T> {
+    /// Writes the transition graph of this chain to 'writer'
+    /// in the Graphviz DOT language.
+    ///
+    /// Contexts are rendered as nodes, and each outcome of a
+    /// context is rendered as an edge labeled with its
+    /// probability and weight. Output is sorted by label, then
+    /// by the bytes each element feeds to Hash, so that the
+    /// same model always renders the same way.
+    pub fn to_dot<W: Write>(&self, mut writer: W, options: &DotOptions<T>) -> std::io::Result<()> {
+        let mut contexts: Vec<_> = self
+            .probability_map
+            .iter()
+            .filter(|(k, _)| options.include_partial || k.iter().all(|e| e.is_some()))
+            .map(|(k, d)| (options.context_label(&k), k, d))
+            .collect();
+        // Labels can be the same for different contexts, so ties
+        // are broken by the bytes the contexts hash.
+        contexts.sort_by_cached_key(|c| (c.0.clone(), sort_key(&c.1)));
+
+        // Node ids are handed out in the order nodes are first seen.
+        // Nodes are told apart by what they stand for, since
+        // different elements may have the same label.
+        let mut ids: HashMap<Node<T>, String> = HashMap::new();
+        let mut nodes: Vec<(String, String, &str)> = vec![];
+        let mut node_id = |node: Node<T>, label: String| -> String {
+            if let Some(id) = ids.get(&node) {
+                return id.clone();
+            }
+            let (prefix, shape) = match node {
+                Node::Context(_) => ("c", "ellipse"),
+                Node::Outcome(_) => ("o", "box"),
+            };
+            let id = format!("{}{}", prefix, ids.len());
+            ids.insert(node, id.clone());
+            nodes.push((id.clone(), label, shape));
+            id
+        };
+
+        let mut edges: Vec<(String, String, String)> = vec![];
+        for (label, context, die) in contexts {
+            let from = node_id(Node::Context(context.clone()), label);
+            let total = die.total_weight();
+
+            let mut sides: Vec<(T, u64, String)> = die
+                .iter()
+                .filter(|s| s.1 > 0 && s.1 as f32 / total as f32 >= options.threshold)
+                .map(|s| {
+                    let label = (options.label)(&s.0);
+                    (s.0, s.1, label)
+                })
+                .collect();
+            sides.sort_by_cached_key(|s| (Reverse(s.1), s.2.clone(), sort_key(&s.0)));
+            if let Some(k) = options.top_k {
+                sides.truncate(k);
+            }
+
+            for (outcome, weight, outcome_label) in sides {
+                let to = match options.edges {
+                    DotEdges::NextContext => {
+                        // Slide the window forward. An order 0 chain
+                        // always stays in its only context.
+                        let mut next = context.clone();
+                        if !next.is_empty() {
+                            next.remove(0);
+                            next.push(Some(outcome));
+                        }
+                        let label = options.context_label(&next);
+                        node_id(Node::Context(next), label)
+                    }
+                    DotEdges::Outcome => node_id(Node::Outcome(outcome), outcome_label),
+                };
+                let edge_label = format!("{:.3} ({})", weight as f64 / total as f64, weight);
+                edges.push((from.clone(), to, edge_label));
+            }
+        }
+
+        writeln!(writer, "digraph markov {{")?;
+        for (id, label, shape) in nodes {
+            writeln!(
+                writer,
+                "    {} [label={}, shape={}];",
+                id,
+                quote(&label),
+                shape
+            )?;
+        }
+        for (from, to, label) in edges {
+            writeln!(writer, "    {} -> {} [label={}];", from, to, quote(&label))?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(m: &MarkovChain<char>, options: &DotOptions<char>) -> String {
+        let mut out = vec![];
+        m.to_dot(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn chain() -> MarkovChain<char> {
+        let mut m = MarkovChain::new(1, &[]);
+        m.train(&['a'], 'b', 3);
+        m.train(&['a'], 'c', 1);
+        m.train(&['b'], 'a', 1);
+        m
+    }
+
+    #[test]
+    fn next_context() {
+        let dot = render(&chain(), &DotOptions::new(|c: &char| c.to_string()));
+        assert!(dot.starts_with("digraph markov {"));
+        assert!(dot.contains("c0 [label=\"a\", shape=ellipse];"));
+        assert!(dot.contains("c1 [label=\"b\", shape=ellipse];"));
+        assert!(dot.contains("c2 [label=\"c\", shape=ellipse];"));
+        assert!(dot.contains("c0 -> c1 [label=\"0.750 (3)\"];"));
+        assert!(dot.contains("c0 -> c2 [label=\"0.250 (1)\"];"));
+        assert!(dot.contains("c1 -> c0 [label=\"1.000 (1)\"];"));
+    }
+
+    #[test]
+    fn outcome_edges() {
+        let options =
+            DotOptions::new(|c: &char| c.to_uppercase().to_string()).edges(DotEdges::Outcome);
+        let dot = render(&chain(), &options);
+        assert!(dot.contains("o1 [label=\"B\", shape=box];"));
+        assert!(dot.contains("c0 -> o1 [label=\"0.750 (3)\"];"));
+    }
+
+    #[test]
+    fn filtering() {
+        let dot = render(
+            &chain(),
+            &DotOptions::new(|c: &char| c.to_string()).threshold(0.5),
+        );
+        assert!(dot.contains("c0 -> c1"));
+        assert!(!dot.contains("c0 -> c2"));
+
+        let dot = render(
+            &chain(),
+            &DotOptions::new(|c: &char| c.to_string()).top_k(1),
+        );
+        assert!(dot.contains("c0 -> c1"));
+        assert!(!dot.contains("c0 -> c2"));
+    }
+
+    #[test]
+    fn same_labels_render_the_same_way() {
+        // Two equal chains, trained in opposite orders, hold their
+        // contexts in different HashMap orders.
+        let letters: Vec<char> = ('a'..='z').collect();
+        let mut forward = MarkovChain::new(1, &[]);
+        let mut backward = MarkovChain::new(1, &[]);
+        for (&a, &b) in letters.iter().zip(letters.iter().skip(1)) {
+            forward.train(&[a], b, 1);
+            forward.train(&[a], 'z', 1);
+        }
+        for (&a, &b) in letters.iter().zip(letters.iter().skip(1)).rev() {
+            backward.train(&[a], 'z', 1);
+            backward.train(&[a], b, 1);
+        }
+
+        let options = DotOptions::new(|_: &char| "x".to_string());
+        assert_eq!(render(&forward, &options), render(&backward, &options));
+        let options = options.edges(DotEdges::Outcome);
+        assert_eq!(render(&forward, &options), render(&backward, &options));
+    }
+
+    #[test]
+    fn same_labels() {
+        // Every element renders the same, but the nodes stay
+        // apart.
+        let dot = render(&chain(), &DotOptions::new(|_: &char| "x".to_string()));
+        assert_eq!(dot.matches("shape=ellipse").count(), 3);
+        assert_eq!(dot.matches(" -> ").count(), 3);
+
+        let options = DotOptions::new(|_: &char| "x".to_string()).edges(DotEdges::Outcome);
+        let dot = render(&chain(), &options);
+        assert_eq!(dot.matches("shape=box").count(), 3);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(quote("say \"hi\" \\"), "\"say \\\"hi\\\" \\\\\"");
+    }
+}
@@ -0,0 +1,290 @@
+use cfg_if::cfg_if;
+use std::hash::{Hash, Hasher};
+
+use super::die::{find_side, resolve_roll, WeightedDie};
+use super::{Element, MarkovChain};
+
+/// 64-bit FNV-1a. Unlike the default hasher, this one
+/// is deterministic and cheap for short keys.
+pub(crate) struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+pub(crate) fn hash_key<T: Element>(key: &[Option<T>]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A read-only MarkovChain, optimized for generation.
+///
+/// All contexts and dies are packed into a handful of
+/// contiguous arrays, sorted by the hash of the context.
+/// Lookups are a binary search over the hashes, and rolls
+/// are a binary search over the matching die's running
+/// weights.
+///
+/// Create one with MarkovChain::freeze.
+#[derive(Clone)]
+pub struct FrozenMarkovChain<T: Element> {
+    order: usize,
+    optional_elements: Vec<usize>,
+    // sorted hash of each context.
+    hashes: Vec<u64>,
+    // context i is contexts[i * order..(i + 1) * order].
+    contexts: Vec<Option<T>>,
+    // the sides of die i are items[offsets[i]..offsets[i + 1]].
+    offsets: Vec<usize>,
+    items: Vec<T>,
+    // running weights restart at each die.
+    running_weight: Vec<u64>,
+}
+
+impl<T: Element> MarkovChain<T> {
+    /// Packs this chain into a read-only FrozenMarkovChain.
+    ///
+    /// The frozen chain generates the same values as this
+    /// one for the same rolls, but uses less memory and is
+    /// friendlier to the cache.
+    ///
+    /// Frozen chains only look up exact contexts, so the
+    /// partial contexts of a chain with Indexing::Wildcard
+    /// are worked out ahead of time, for its optional
+    /// elements: 2^k of them for each full context, with k
+    /// optional elements.
+    pub fn freeze(&self) -> FrozenMarkovChain<T> {
+        let map = self.expanded_map();
+        let mut dies: Vec<_> = map
+            .iter()
+            .filter(|(_, d)| d.total_weight() > 0)
+            .map(|(k, d)| (hash_key(&k), k, d))
+            .collect();
+        dies.sort_unstable_by_key(|d| d.0);
+
+        let mut frozen = FrozenMarkovChain {
+            order: self.order,
+            optional_elements: self.optional_elements.clone(),
+            hashes: Vec::with_capacity(dies.len()),
+            contexts: Vec::with_capacity(dies.len() * self.order),
+            offsets: Vec::with_capacity(dies.len() + 1),
+            items: vec![],
+            running_weight: vec![],
+        };
+        frozen.offsets.push(0);
+        for (hash, key, die) in dies {
+            frozen.hashes.push(hash);
+            frozen.contexts.extend_from_slice(&key);
+            let mut running = 0;
+            for (e, weight) in die.iter().filter(|s| s.1 > 0) {
+                running += weight;
+                frozen.items.push(e);
+                frozen.running_weight.push(running);
+            }
+            frozen.offsets.push(frozen.items.len());
+        }
+        frozen.items.shrink_to_fit();
+        frozen.running_weight.shrink_to_fit();
+        frozen
+    }
+}
+
+impl<T: Element> FrozenMarkovChain<T> {
+    /// Returns the order of the chain this was frozen from.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Returns the optional elements of the chain this was
+    /// frozen from.
+    pub fn optional_elements(&self) -> &[usize] {
+        &self.optional_elements
+    }
+
+    /// Returns the number of contexts with at least one outcome.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Returns true if no context has any outcomes.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Returns context 'i', in hash order.
+    pub(crate) fn context(&self, i: usize) -> &[Option<T>] {
+        &self.contexts[i * self.order..(i + 1) * self.order]
+    }
+
+    /// Returns the sides and running weights of die 'i'.
+    pub(crate) fn sides(&self, i: usize) -> (&[T], &[u64]) {
+        let range = self.offsets[i]..self.offsets[i + 1];
+        (&self.items[range.clone()], &self.running_weight[range])
+    }
+
+    /// Finds the index of the die for 'key'.
+    fn find(&self, key: &[Option<T>]) -> Option<usize> {
+        let hash = hash_key(key);
+        let start = self.hashes.partition_point(|h| *h < hash);
+        (start..self.hashes.len())
+            .take_while(|i| self.hashes[*i] == hash)
+            .find(|i| self.context(*i) == key)
+    }
+
+    fn roll(&self, key: &[Option<T>], roll: Option<u64>) -> Option<T> {
+        let die = self.find(key)?;
+        let running = &self.running_weight[self.offsets[die]..self.offsets[die + 1]];
+        let roll_result = resolve_roll(*running.last()?, roll);
+        Some(self.items[self.offsets[die] + find_side(running, roll_result)].clone())
+    }
+
+    /// Keeps the last 'order' elements of the view.
+    fn tail<'a, E>(&self, view: &'a [E]) -> &'a [E] {
+        &view[view.len() - self.order..]
+    }
+
+    /// Generates the next value, given the previous item(s).
+    ///
+    /// view is the sliding window of the latest elements.
+    /// only the last self.order elements are looked at.
+    ///
+    /// rand_val allows for a deterministic result, if supplied.
+    pub fn generate_deterministic_from_partial(
+        &self,
+        view: &[Option<T>],
+        rand_val: u64,
+    ) -> Option<T> {
+        self.roll(self.tail(view), Some(rand_val))
+    }
+
+    /// Generates the next value, given the previous item(s).
+    ///
+    /// view is the sliding window of the latest elements.
+    /// only the last self.order elements are looked at.
+    ///
+    /// rand_val allows for a deterministic result, if supplied.
+    pub fn generate_deterministic(&self, view: &[T], rand_val: u64) -> Option<T> {
+        let key: Vec<Option<T>> = self.tail(view).iter().map(|e| Some(e.clone())).collect();
+        self.roll(&key, Some(rand_val))
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "rand")] {
+            /// Generates the next value, given the previous item(s).
+            ///
+            /// view is the sliding window of the latest elements.
+            /// only the last self.order elements are looked at.
+            pub fn generate(&self, view: &[T]) -> Option<T> {
+                let key: Vec<Option<T>> = self.tail(view).iter().map(|e| Some(e.clone())).collect();
+                self.roll(&key, None)
+            }
+
+            /// Generates the next value, given the previous item(s).
+            ///
+            /// view is the sliding window of the latest elements.
+            /// only the last self.order elements are looked at.
+            pub fn generate_from_partial(&self, view: &[Option<T>]) -> Option<T> {
+                self.roll(self.tail(view), None)
+            }
+        }
+    }
+
+    /// Returns the probability of getting 'result', given
+    /// 'view'.
+    pub fn probability(&self, view: &[Option<T>], result: &T) -> f32 {
+        let die = match self.find(self.tail(view)) {
+            Some(d) => d,
+            None => return 0.0,
+        };
+        let (start, end) = (self.offsets[die], self.offsets[die + 1]);
+        match self.items[start..end].iter().position(|e| e == result) {
+            Some(i) => {
+                let weight = match i {
+                    0 => self.running_weight[start],
+                    _ => self.running_weight[start + i] - self.running_weight[start + i - 1],
+                };
+                WeightedDie::<T>::less_lossy_divide(weight, self.running_weight[end - 1])
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn months() -> MarkovChain<char> {
+        let mut m = MarkovChain::new(2, &[0]);
+        for word in ["january", "february", "march", "april", "may", "june"].iter() {
+            let w: Vec<char> = format!("$%{} ", word).chars().collect();
+            for i in 2..w.len() {
+                m.train(&[w[i - 2], w[i - 1]], w[i], 1);
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn empty() {
+        let m: MarkovChain<u8> = MarkovChain::new(1, &[]);
+        let f = m.freeze();
+        assert!(f.is_empty());
+        assert_eq!(f.generate_deterministic(&[1], 3), None);
+        assert_eq!(f.probability(&[Some(1)], &1), 0.0);
+    }
+
+    #[test]
+    fn order_zero() {
+        let mut m = MarkovChain::new(0, &[]);
+        m.train(&[], 'a', 1);
+        m.train(&[], 'b', 3);
+        let f = m.freeze();
+        assert_eq!(f.len(), 1);
+        assert_eq!(f.generate_deterministic(&[], 0), Some('a'));
+        assert_eq!(f.generate_deterministic(&[], 1), Some('b'));
+        assert_eq!(f.probability(&[], &'b'), 0.75);
+    }
+
+    #[test]
+    fn matches_chain() {
+        let m = months();
+        let f = m.freeze();
+        assert_eq!(f.order(), 2);
+        assert_eq!(f.optional_elements(), &[0]);
+        assert_eq!(f.len(), m.probability_map.values().count());
+
+        for (key, die) in m.probability_map.iter() {
+            for roll in 0..die.total_weight() * 2 {
+                assert_eq!(
+                    f.generate_deterministic_from_partial(&key, roll),
+                    m.generate_deterministic_from_partial(&key, roll)
+                );
+            }
+            for (e, _) in die.iter() {
+                assert_eq!(f.probability(&key, &e), m.probability(&key, &e));
+            }
+        }
+        assert_eq!(
+            f.generate_deterministic(&['x', 'm', 'a'], 0),
+            m.generate_deterministic(&['x', 'm', 'a'], 0)
+        );
+        assert_eq!(f.generate_deterministic(&['z', 'z'], 0), None);
+    }
+}
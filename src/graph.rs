@@ -0,0 +1,326 @@
+//! Labeling the nodes of graphs, like road networks or the
+//! rooms of a dungeon.
+//!
+//! Nodes in a graph have any number of neighbors, in no
+//! particular order, so a GraphModel is given a function that
+//! turns a node's neighbor labels into a view of a fixed size.
+//! Neighbors that aren't labeled yet are None, and the
+//! function should leave whatever they'd decide None too.
+//!
+//! Views are only trained on once they're complete. Pad out
+//! small neighborhoods with a label of your own, rather than
+//! None.
+
+use super::grid::{GenerateOptions, GridError, Traversal};
+use super::rng::SplitMix64;
+use super::topology::{visit_order, Topology, TopologyModel, Views};
+use super::{Element, MarkovChain};
+
+/// A graph, as the list of neighbors of each node.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Graph {
+    adjacency: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    /// Creates a graph of 'nodes' nodes and no edges.
+    pub fn new(nodes: usize) -> Self {
+        Graph {
+            adjacency: vec![vec![]; nodes],
+        }
+    }
+
+    /// Creates a graph of 'nodes' nodes, joined by 'edges'.
+    ///
+    /// Panics if an edge has a node that's out of range.
+    pub fn from_edges(nodes: usize, edges: &[(usize, usize)]) -> Self {
+        let mut graph = Graph::new(nodes);
+        for (a, b) in edges {
+            graph.add_edge(*a, *b);
+        }
+        graph
+    }
+
+    /// Joins 'a' and 'b', making each a neighbor of the other.
+    ///
+    /// Panics if either node is out of range.
+    pub fn add_edge(&mut self, a: usize, b: usize) {
+        let nodes = self.adjacency.len();
+        assert!(
+            a < nodes && b < nodes,
+            "edge ({}, {}) is outside a graph of {} nodes",
+            a,
+            b,
+            nodes
+        );
+        self.adjacency[a].push(b);
+        if a != b {
+            self.adjacency[b].push(a);
+        }
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.adjacency.len()
+    }
+
+    /// Returns true if the graph has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.adjacency.is_empty()
+    }
+
+    /// Returns the neighbors of 'node', in the order their
+    /// edges were added.
+    pub fn neighbors(&self, node: usize) -> &[usize] {
+        &self.adjacency[node]
+    }
+}
+
+/// A graph, and the view function that reads it.
+struct Labeled<'a, F> {
+    graph: &'a Graph,
+    view: &'a F,
+    size: usize,
+}
+
+impl<'a, F> Topology for Labeled<'a, F> {
+    fn dims(&self) -> Vec<usize> {
+        vec![self.graph.len()]
+    }
+
+    fn neighbors(&self, cell: usize) -> Vec<Option<usize>> {
+        self.graph
+            .neighbors(cell)
+            .iter()
+            .copied()
+            .map(Some)
+            .collect()
+    }
+}
+
+impl<'a, T: Clone, F: Fn(&[Option<T>]) -> Vec<Option<T>>> Views<T> for Labeled<'a, F> {
+    fn view(&self, cells: &[Option<T>], cell: usize) -> Vec<Option<T>> {
+        let labels: Vec<Option<T>> = self
+            .graph
+            .neighbors(cell)
+            .iter()
+            .map(|n| cells[*n].clone())
+            .collect();
+        let view = (self.view)(&labels);
+        assert_eq!(
+            view.len(),
+            self.size,
+            "the view of node {} has the wrong size",
+            cell
+        );
+        view
+    }
+}
+
+/// Learns and generates the labels of graph nodes.
+#[derive(Clone)]
+pub struct GraphModel<T: Element, F> {
+    model: TopologyModel<T>,
+    view: F,
+}
+
+impl<T: Element, F: Fn(&[Option<T>]) -> Vec<Option<T>>> GraphModel<T, F> {
+    /// Creates a new GraphModel. 'view' turns the labels of
+    /// a node's neighbors into a view of 'size' elements.
+    ///
+    /// The chain matches unknown elements of a view when
+    /// generating. See Indexing::Wildcard.
+    pub fn new(size: usize, view: F) -> Self {
+        GraphModel {
+            model: TopologyModel::new(size),
+            view,
+        }
+    }
+
+    /// Returns the underlying chain.
+    pub fn chain(&self) -> &MarkovChain<T> {
+        self.model.chain()
+    }
+
+    fn labeled<'a>(&'a self, graph: &'a Graph) -> Labeled<'a, F> {
+        Labeled {
+            graph,
+            view: &self.view,
+            size: self.model.degree(),
+        }
+    }
+
+    /// Returns the view of 'node', given the 'labels' of
+    /// every node.
+    ///
+    /// Panics if the view function returns a view of the
+    /// wrong size.
+    pub fn view(&self, graph: &Graph, labels: &[Option<T>], node: usize) -> Vec<Option<T>> {
+        self.labeled(graph).view(labels, node)
+    }
+
+    /// Feeds every labeled node whose view is complete into
+    /// the model.
+    pub fn train(&mut self, graph: &Graph, labels: &[Option<T>]) {
+        let views = (0..graph.len())
+            .map(|n| self.view(graph, labels, n))
+            .collect();
+        self.model.train_views(labels, views);
+    }
+
+    /// Labels every node that's None in 'labels', visiting the
+    /// nodes in the order of the traversal in 'options'.
+    /// RowMajor goes by node number, and MostConstrained
+    /// visits the node with the most labeled neighbors next.
+    ///
+    /// Nodes that already have a label keep it. They're
+    /// visited in turn like the rest, and their label must be
+    /// one the model could have generated there.
+    ///
+    /// Panics if 'labels' doesn't have one entry per node.
+    pub fn label(
+        &self,
+        graph: &Graph,
+        labels: &[Option<T>],
+        options: &GenerateOptions,
+    ) -> Result<Vec<T>, GridError<usize>> {
+        let mut rng = SplitMix64::from_seed(options.seed);
+        let order = visit_order(&self.labeled(graph), options.traversal, &mut rng);
+        self.label_order(graph, labels, options, &order, &mut rng)
+    }
+
+    /// Labels every node that's None in 'labels', visiting the
+    /// nodes in 'order', then any it leaves out by node
+    /// number. The traversal in 'options' is ignored.
+    ///
+    /// Panics if 'labels' doesn't have one entry per node, or
+    /// 'order' lists a node that's out of range.
+    pub fn label_in_order(
+        &self,
+        graph: &Graph,
+        labels: &[Option<T>],
+        order: &[usize],
+        options: &GenerateOptions,
+    ) -> Result<Vec<T>, GridError<usize>> {
+        let mut listed = vec![false; graph.len()];
+        for n in order {
+            assert!(
+                *n < graph.len(),
+                "node {} is outside a graph of {} nodes",
+                n,
+                graph.len()
+            );
+            listed[*n] = true;
+        }
+        let rest = (0..graph.len()).filter(|n| !listed[*n]);
+        let order: Vec<usize> = order.iter().copied().chain(rest).collect();
+        let options = options.clone().traversal(Traversal::RowMajor);
+        let mut rng = SplitMix64::from_seed(options.seed);
+        self.label_order(graph, labels, &options, &order, &mut rng)
+    }
+
+    fn label_order(
+        &self,
+        graph: &Graph,
+        labels: &[Option<T>],
+        options: &GenerateOptions,
+        order: &[usize],
+        rng: &mut SplitMix64,
+    ) -> Result<Vec<T>, GridError<usize>> {
+        assert_eq!(
+            labels.len(),
+            graph.len(),
+            "every node needs a label or None"
+        );
+        self.model
+            .fill_views(
+                &self.labeled(graph),
+                labels,
+                options,
+                order,
+                &mut |_| rng.next_u64(),
+                &|_| true,
+            )
+            .map_err(|e| e.map_cell(|c| c[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::Policy;
+    use super::*;
+
+    /// Whether any neighbor is an 'a', then whether any is a
+    /// 'b', with '-' for no. While some neighbors are unknown,
+    /// a no is unknown too.
+    fn kinds(labels: &[Option<char>]) -> Vec<Option<char>> {
+        let unknown = labels.iter().any(Option::is_none);
+        ['a', 'b']
+            .iter()
+            .map(|k| match labels.contains(&Some(*k)) {
+                true => Some(*k),
+                false if unknown => None,
+                false => Some('-'),
+            })
+            .collect()
+    }
+
+    fn colored(graph: &Graph, labels: &[char]) -> bool {
+        (0..graph.len()).all(|n| graph.neighbors(n).iter().all(|m| labels[*m] != labels[n]))
+    }
+
+    #[test]
+    fn two_colorings() {
+        // A tree, where neighbors always differ.
+        let tree = Graph::from_edges(7, &[(0, 1), (0, 2), (1, 3), (1, 4), (2, 5), (2, 6)]);
+        let labels = ['a', 'b', 'b', 'a', 'a', 'a', 'a'].map(Some);
+        let mut m = GraphModel::new(2, kinds);
+        m.train(&tree, &labels);
+        assert_eq!(m.view(&tree, &labels, 1), vec![Some('a'), Some('-')]);
+
+        // An even cycle with a tail.
+        let other = Graph::from_edges(
+            8,
+            &[
+                (0, 1),
+                (1, 2),
+                (2, 3),
+                (3, 4),
+                (4, 5),
+                (5, 0),
+                (5, 6),
+                (6, 7),
+            ],
+        );
+        let options = GenerateOptions::new()
+            .traversal(Traversal::MostConstrained)
+            .policy(Policy::Backtrack(1000))
+            .seed(7);
+        let found = m.label(&other, &[None; 8], &options).unwrap();
+        assert!(colored(&other, &found));
+
+        let triangle = Graph::from_edges(3, &[(0, 1), (1, 2), (2, 0)]);
+        assert_eq!(
+            m.label(&triangle, &[None; 3], &options),
+            Err(GridError::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn chosen_order() {
+        let path = Graph::from_edges(5, &[(0, 1), (1, 2), (2, 3), (3, 4)]);
+        let mut m = GraphModel::new(2, kinds);
+        m.train(&path, &['a', 'b', 'a', 'b', 'a'].map(Some));
+
+        // Outward from a labeled middle node, every node has
+        // one labeled neighbor when it's visited, so a single
+        // pass never gets stuck.
+        let mut seeded = [None; 5];
+        seeded[2] = Some('b');
+        let options = GenerateOptions::new().policy(Policy::Retry(1)).seed(3);
+        let found = m
+            .label_in_order(&path, &seeded, &[2, 1, 3, 0], &options)
+            .unwrap();
+        assert_eq!(found, vec!['b', 'a', 'b', 'a', 'b']);
+    }
+}
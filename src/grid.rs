@@ -0,0 +1,712 @@
+//! Generation of 2D grids, like tilemaps.
+//!
+//! A GridModel learns which values appear next to which from
+//! example grids. Each cell's view is its neighborhood: the
+//! cells at a fixed set of offsets from it. Every neighbor is
+//! an optional element, so a cell can be generated from
+//! whichever of its neighbors are already filled in.
+
+use super::constraint::Constraint;
+use super::topology::{Lattice, Refinement, TopologyModel};
+use super::{Element, MarkovChain};
+
+/// A rectangular grid of cells, each of which may be empty.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<Option<T>>,
+}
+
+impl<T: Clone> Grid<T> {
+    /// Creates an empty grid.
+    pub fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![None; width * height],
+        }
+    }
+
+    /// Creates a full grid from rows of values. The grid is
+    /// as wide as the longest row, and the cells past the end
+    /// of shorter rows are left empty.
+    pub fn from_rows<R: AsRef<[T]>>(rows: &[R]) -> Self {
+        let width = rows.iter().map(|r| r.as_ref().len()).max().unwrap_or(0);
+        let mut grid = Grid::new(width, rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, v) in row.as_ref().iter().enumerate() {
+                grid.set(x, y, Some(v.clone()));
+            }
+        }
+        grid
+    }
+
+    /// Returns the number of columns.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of rows.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the value at column 'x' and row 'y', or None
+    /// if the cell is empty or outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        match x < self.width && y < self.height {
+            true => self.cells[y * self.width + x].as_ref(),
+            false => None,
+        }
+    }
+
+    /// Sets the value at column 'x' and row 'y'.
+    ///
+    /// Panics if the cell is outside the grid.
+    pub fn set(&mut self, x: usize, y: usize, value: Option<T>) {
+        assert!(
+            x < self.width && y < self.height,
+            "({}, {}) is outside a {}x{} grid",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        self.cells[y * self.width + x] = value;
+    }
+
+    /// Iterates over the rows, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[Option<T>]> + '_ {
+        self.cells.chunks(self.width.max(1))
+    }
+
+    /// Returns every cell, row by row.
+    pub fn cells(&self) -> &[Option<T>] {
+        &self.cells
+    }
+
+    /// Returns true if every cell has a value.
+    pub fn is_complete(&self) -> bool {
+        self.cells.iter().all(Option::is_some)
+    }
+
+    /// Returns a copy rotated 90 degrees clockwise, with each
+    /// value passed through 'rotate'.
+    pub fn rotated(&self, rotate: impl Fn(&T) -> T) -> Self {
+        let mut out = Grid::new(self.height, self.width);
+        for y in 0..out.height {
+            for x in 0..out.width {
+                let cell = self.cells[(self.height - 1 - x) * self.width + y].as_ref();
+                out.set(x, y, cell.map(&rotate));
+            }
+        }
+        out
+    }
+
+    /// Returns a copy flipped left to right, with each value
+    /// passed through 'mirror'.
+    pub fn mirrored(&self, mirror: impl Fn(&T) -> T) -> Self {
+        let mut out = Grid::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.cells[y * self.width + self.width - 1 - x].as_ref();
+                out.set(x, y, cell.map(&mirror));
+            }
+        }
+        out
+    }
+}
+
+/// Transforms a single tile.
+type TileMap<'a, T> = Box<dyn Fn(&T) -> T + 'a>;
+
+/// The rotations and reflections a tile set is the same
+/// under, for training on every orientation of a grid.
+///
+/// Each transform comes with a function that transforms a
+/// single tile, like turning '┏' into '┓'.
+pub struct Symmetry<'a, T> {
+    rotate: Option<TileMap<'a, T>>,
+    mirror: Option<TileMap<'a, T>>,
+}
+
+impl<'a, T: Clone> Default for Symmetry<'a, T> {
+    fn default() -> Self {
+        Symmetry {
+            rotate: None,
+            mirror: None,
+        }
+    }
+}
+
+impl<'a, T: Clone> Symmetry<'a, T> {
+    /// Creates a Symmetry with no transforms, which only
+    /// keeps the original grid.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the rotations by 90, 180 and 270 degrees.
+    /// 'rotate' turns a tile 90 degrees clockwise.
+    pub fn rotations<F: Fn(&T) -> T + 'a>(mut self, rotate: F) -> Self {
+        self.rotate = Some(Box::new(rotate));
+        self
+    }
+
+    /// Adds the mirror image, and the mirror image of each
+    /// rotation. 'mirror' flips a tile left to right.
+    pub fn reflections<F: Fn(&T) -> T + 'a>(mut self, mirror: F) -> Self {
+        self.mirror = Some(Box::new(mirror));
+        self
+    }
+
+    /// Returns 'grid' in every orientation, starting with the
+    /// original. There are 1, 2, 4 or 8 of them.
+    pub fn orientations(&self, grid: &Grid<T>) -> Vec<Grid<T>> {
+        let mut all = vec![grid.clone()];
+        if let Some(rotate) = &self.rotate {
+            for i in 0..3 {
+                let next = all[i].rotated(rotate);
+                all.push(next);
+            }
+        }
+        if let Some(mirror) = &self.mirror {
+            let mirrored: Vec<Grid<T>> = all.iter().map(|g| g.mirrored(mirror)).collect();
+            all.extend(mirrored);
+        }
+        all
+    }
+}
+
+/// Which cells around a cell make up its view.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Neighborhood {
+    /// The 4 orthogonal neighbors: up, left, right, down.
+    VonNeumann,
+    /// All 8 surrounding cells, in row-major order.
+    Moore,
+    /// The 6 neighbors of a hex in axial coordinates, with q
+    /// as x and r as y: east, northeast, northwest, west,
+    /// southwest, southeast.
+    Hex,
+    /// Any (dx, dy) offsets, in view order. y grows downward.
+    Custom(Vec<(isize, isize)>),
+}
+
+impl Neighborhood {
+    /// Returns the (dx, dy) offsets of each neighbor, in the
+    /// order they appear in the view.
+    pub fn offsets(&self) -> Vec<(isize, isize)> {
+        match self {
+            Neighborhood::VonNeumann => vec![(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Neighborhood::Moore => vec![
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+            Neighborhood::Hex => vec![(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)],
+            Neighborhood::Custom(offsets) => offsets.clone(),
+        }
+    }
+}
+
+/// The order empty cells are filled in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Traversal {
+    /// Left to right, then top to bottom.
+    RowMajor,
+    /// Top to bottom, then left to right.
+    ColumnMajor,
+    /// Row by row, alternating direction.
+    Serpentine,
+    /// A random order, taken from the seed.
+    Shuffled,
+    /// Always the empty cell with the most filled neighbors.
+    /// Ties go to the first in row-major order.
+    MostConstrained,
+}
+
+/// What to do when a cell's neighbors match nothing the
+/// model was trained on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Start over from the initial grid, at most this many
+    /// times in total.
+    Retry(usize),
+    /// Undo the latest choices and try other values instead,
+    /// placing at most this many values in total.
+    Backtrack(usize),
+}
+
+/// Controls how GridModel fills in a grid.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GenerateOptions {
+    pub(crate) traversal: Traversal,
+    pub(crate) policy: Policy,
+    pub(crate) seed: Option<u64>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            traversal: Traversal::RowMajor,
+            policy: Policy::Retry(100),
+            seed: None,
+        }
+    }
+}
+
+impl GenerateOptions {
+    /// Creates new options.
+    ///
+    /// By default, cells are filled in row-major order, the
+    /// grid is retried up to 100 times, and rolls come from
+    /// the thread rng.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the order cells are filled in.
+    pub fn traversal(mut self, traversal: Traversal) -> Self {
+        self.traversal = traversal;
+        self
+    }
+
+    /// Sets what happens when a cell can't be filled.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Takes every roll from 'seed', so the same seed always
+    /// gives the same grid. Required without the 'rand'
+    /// feature.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// Errors from filling in a grid.
+///
+/// Cells are given as (x, y) for grids. TopologyModel gives
+/// the coordinates of a cell instead, and GraphModel gives
+/// the node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GridError<C = (usize, usize)> {
+    /// Every retry ran into a cell whose neighbors match
+    /// nothing the model was trained on, or a filled cell
+    /// whose neighbors don't allow its value. Holds that
+    /// cell in the last attempt.
+    Contradiction(C),
+    /// Every retry placed a value after which the constraints
+    /// could no longer be met. Holds that cell in the last
+    /// attempt.
+    Constraint(C),
+    /// Backtracking placed its maximum number of values.
+    OutOfSteps,
+    /// Backtracking tried every value, and nothing fits, or
+    /// the fixed cells already break a constraint.
+    Unsatisfiable,
+}
+
+impl<C> GridError<C> {
+    /// Changes how the cell is given, with 'f'.
+    pub(crate) fn map_cell<D>(self, f: impl FnOnce(C) -> D) -> GridError<D> {
+        match self {
+            GridError::Contradiction(c) => GridError::Contradiction(f(c)),
+            GridError::Constraint(c) => GridError::Constraint(f(c)),
+            GridError::OutOfSteps => GridError::OutOfSteps,
+            GridError::Unsatisfiable => GridError::Unsatisfiable,
+        }
+    }
+}
+
+impl<C: std::fmt::Debug> std::fmt::Display for GridError<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GridError::Contradiction(cell) => {
+                write!(f, "no trained value fits the neighbors of {:?}", cell)
+            }
+            GridError::Constraint(cell) => {
+                write!(f, "the constraints can't be met after filling {:?}", cell)
+            }
+            GridError::OutOfSteps => write!(f, "backtracking ran out of steps"),
+            GridError::Unsatisfiable => write!(f, "no assignment fits the grid"),
+        }
+    }
+}
+
+impl<C: std::fmt::Debug> std::error::Error for GridError<C> {}
+
+/// Learns and generates 2D grids.
+#[derive(Clone)]
+pub struct GridModel<T: Element> {
+    neighborhood: Neighborhood,
+    offsets: Vec<(isize, isize)>,
+    model: TopologyModel<T>,
+}
+
+impl<T: Element> GridModel<T> {
+    /// Creates a new GridModel that looks at 'neighborhood'.
+    ///
+    /// The chain stores only full neighborhoods, and matches
+    /// unknown neighbors when generating. See
+    /// Indexing::Wildcard.
+    pub fn new(neighborhood: Neighborhood) -> Self {
+        let offsets = neighborhood.offsets();
+        GridModel {
+            model: TopologyModel::new(offsets.len()),
+            neighborhood,
+            offsets,
+        }
+    }
+
+    /// Treats every cell outside the grid as 'border', both
+    /// when training and when generating.
+    ///
+    /// Without a border, cells whose neighborhood leaves the
+    /// grid aren't trained on, and outside neighbors are
+    /// unknown when generating.
+    pub fn border(mut self, border: T) -> Self {
+        self.model = self.model.border(border);
+        self
+    }
+
+    /// Returns the (dx, dy) offset of each neighbor, in view
+    /// order.
+    pub fn offsets(&self) -> &[(isize, isize)] {
+        &self.offsets
+    }
+
+    /// Returns the underlying chain.
+    pub fn chain(&self) -> &MarkovChain<T> {
+        self.model.chain()
+    }
+
+    pub(crate) fn topology(&self) -> &TopologyModel<T> {
+        &self.model
+    }
+
+    fn lattice(&self, grid: &Grid<T>) -> Lattice {
+        Lattice::grid(grid.width, grid.height, &self.neighborhood)
+    }
+
+    /// Returns the view of the cell at (x, y): its neighbors,
+    /// None where they're empty.
+    pub fn view(&self, grid: &Grid<T>, x: usize, y: usize) -> Vec<Option<T>> {
+        self.model
+            .view(&self.lattice(grid), &grid.cells, y * grid.width + x)
+    }
+
+    /// Feeds every filled cell of 'grid' whose neighbors are
+    /// all known into the model.
+    pub fn train(&mut self, grid: &Grid<T>) {
+        let lattice = self.lattice(grid);
+        self.model.train(&lattice, &grid.cells);
+    }
+
+    /// Feeds 'grid' into the model in every orientation of
+    /// 'symmetry'. Neighbors land in the view positions they
+    /// have after the transform, so a tile that's to the left
+    /// of another becomes above it after a rotation.
+    ///
+    /// Panics if the neighborhood is Hex and 'symmetry' turns
+    /// or flips the grid, since hexes don't line up after a
+    /// quarter turn.
+    pub fn train_symmetric(&mut self, grid: &Grid<T>, symmetry: &Symmetry<T>) {
+        let moves = symmetry.rotate.is_some() || symmetry.mirror.is_some();
+        assert!(
+            !(moves && self.neighborhood == Neighborhood::Hex),
+            "hex grids can't be turned or flipped"
+        );
+        for g in symmetry.orientations(grid) {
+            self.train(&g);
+        }
+    }
+
+    /// Generates a 'width' by 'height' grid.
+    pub fn generate(
+        &self,
+        width: usize,
+        height: usize,
+        options: &GenerateOptions,
+    ) -> Result<Grid<T>, GridError> {
+        self.fill(&Grid::new(width, height), options)
+    }
+
+    /// Fills in the empty cells of 'grid'.
+    ///
+    /// Cells that already have a value are kept as they are.
+    /// They're visited in turn like the rest, and their value
+    /// must be one the model could have generated there.
+    pub fn fill(&self, grid: &Grid<T>, options: &GenerateOptions) -> Result<Grid<T>, GridError> {
+        self.fill_constrained(grid, &[], options)
+    }
+
+    /// Generates a 'width' by 'height' grid that meets every
+    /// one of 'constraints'.
+    pub fn generate_constrained(
+        &self,
+        width: usize,
+        height: usize,
+        constraints: &[&dyn Constraint<T>],
+        options: &GenerateOptions,
+    ) -> Result<Grid<T>, GridError> {
+        self.fill_constrained(&Grid::new(width, height), constraints, options)
+    }
+
+    /// Fills in the empty cells of 'grid' like fill, so that
+    /// it meets every one of 'constraints'.
+    ///
+    /// The constraints are checked each time a cell is filled.
+    /// When they can't be met anymore, a retry starts over,
+    /// and backtracking tries the cell's next value.
+    pub fn fill_constrained(
+        &self,
+        grid: &Grid<T>,
+        constraints: &[&dyn Constraint<T>],
+        options: &GenerateOptions,
+    ) -> Result<Grid<T>, GridError> {
+        let allows = |cells: &[Option<T>]| {
+            constraints
+                .iter()
+                .all(|c| c.allows(cells, grid.width, grid.height))
+        };
+        let cells = self
+            .model
+            .fill_allowed(&self.lattice(grid), &grid.cells, options, &allows)
+            .map_err(|e| e.map_cell(|c| (c[0], c[1])))?;
+        Ok(Grid {
+            width: grid.width,
+            height: grid.height,
+            cells: cells.into_iter().map(Some).collect(),
+        })
+    }
+
+    /// Returns the (x, y) of every cell in conflict: the empty
+    /// ones, and those whose value never followed their
+    /// neighbors in training.
+    pub fn conflicts(&self, grid: &Grid<T>) -> Vec<(usize, usize)> {
+        self.model
+            .conflicts(&self.lattice(grid), &grid.cells)
+            .into_iter()
+            .map(|c| (c[0], c[1]))
+            .collect()
+    }
+
+    /// Fills in the empty cells of 'grid' roughly, then
+    /// resamples cells until none are in conflict or
+    /// 'max_sweeps' sweeps are done.
+    /// See TopologyModel::refine.
+    pub fn refine(
+        &self,
+        grid: &Grid<T>,
+        max_sweeps: usize,
+        options: &GenerateOptions,
+    ) -> Refinement<Grid<T>, (usize, usize)> {
+        self.model
+            .refine(&self.lattice(grid), &grid.cells, max_sweeps, options)
+            .map(
+                |cells| Grid {
+                    width: grid.width,
+                    height: grid.height,
+                    cells,
+                },
+                |c| (c[0], c[1]),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Grid<char> {
+        let rows: Vec<Vec<char>> = text.lines().map(|l| l.chars().collect()).collect();
+        Grid::from_rows(&rows)
+    }
+
+    fn text(grid: &Grid<char>) -> String {
+        grid.rows()
+            .map(|r| r.iter().map(|c| c.unwrap_or('?')).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn grid_cells() {
+        let mut g = chars("ab\nc");
+        assert_eq!((g.width(), g.height()), (2, 2));
+        assert_eq!(g.get(1, 0), Some(&'b'));
+        assert_eq!(g.get(1, 1), None);
+        assert_eq!(g.get(5, 0), None);
+        assert!(!g.is_complete());
+        g.set(1, 1, Some('d'));
+        assert!(g.is_complete());
+        assert_eq!(text(&g), "ab\ncd");
+    }
+
+    #[test]
+    fn checkerboard() {
+        let mut m = GridModel::new(Neighborhood::VonNeumann).border('#');
+        m.train(&chars("xoxox\noxoxo\nxoxox\noxoxo\nxoxox"));
+        assert_eq!(
+            m.view(&chars("ab\ncd"), 0, 0),
+            vec![Some('#'), Some('#'), Some('b'), Some('c')]
+        );
+
+        for traversal in [
+            Traversal::RowMajor,
+            Traversal::ColumnMajor,
+            Traversal::Serpentine,
+            Traversal::Shuffled,
+            Traversal::MostConstrained,
+        ] {
+            let options = GenerateOptions::new()
+                .traversal(traversal)
+                .policy(Policy::Backtrack(1000))
+                .seed(3);
+            let g = m.generate(5, 5, &options).unwrap();
+            assert!(g.is_complete());
+            // Neighbors always differ.
+            for y in 0..5 {
+                for x in 0..5 {
+                    assert_ne!(g.get(x, y), g.get(x + 1, y));
+                    assert_ne!(g.get(x, y), g.get(x, y + 1));
+                }
+            }
+            assert_eq!(g, m.generate(5, 5, &options).unwrap());
+        }
+    }
+
+    #[test]
+    fn fixed_cells() {
+        let mut m = GridModel::new(Neighborhood::Moore);
+        m.train(&chars("xoxox\noxoxo\nxoxox\noxoxo"));
+        let mut seeded = Grid::new(4, 3);
+        seeded.set(2, 1, Some('o'));
+        let g = m.fill(&seeded, &GenerateOptions::new().seed(0)).unwrap();
+        assert_eq!(text(&g), "xoxo\noxox\nxoxo");
+    }
+
+    #[test]
+    fn dead_ends() {
+        // Each cell sits between one x and one o.
+        let mut m = GridModel::new(Neighborhood::Custom(vec![(-1, 0), (1, 0)]));
+        m.train(&chars("xxooxxoo"));
+        let mut seeded = Grid::new(5, 1);
+        seeded.set(4, 0, Some('o'));
+
+        // Left to right, the middle cell must guess.
+        let retry = GenerateOptions::new().policy(Policy::Retry(1));
+        let failures = (0..20)
+            .filter(|s| m.fill(&seeded, &retry.clone().seed(*s)).is_err())
+            .count();
+        assert!(failures > 0);
+
+        let backtrack = GenerateOptions::new().policy(Policy::Backtrack(100));
+        for seed in 0..20 {
+            let g = m.fill(&seeded, &backtrack.clone().seed(seed)).unwrap();
+            assert_eq!(g.get(2, 0), Some(&'x'));
+        }
+
+        seeded.set(1, 0, Some('o'));
+        seeded.set(3, 0, Some('o'));
+        assert_eq!(
+            m.fill(&seeded, &backtrack.clone().seed(0)),
+            Err(GridError::Unsatisfiable)
+        );
+        assert_eq!(
+            m.fill(&seeded, &retry.clone().seed(0)),
+            Err(GridError::Contradiction((2, 0)))
+        );
+    }
+
+    #[test]
+    fn orientations() {
+        let g = chars("┏━\n┃x");
+        assert_eq!(text(&g.rotated(|c| *c)), "┃┏\nx━");
+        assert_eq!(text(&g.mirrored(|c| *c)), "━┏\nx┃");
+
+        let rotate = |c: &char| match c {
+            '┏' => '┓',
+            '┓' => '┛',
+            '┛' => '┗',
+            '┗' => '┏',
+            '━' => '┃',
+            '┃' => '━',
+            c => *c,
+        };
+        let mirror = |c: &char| match c {
+            '┏' => '┓',
+            '┓' => '┏',
+            '┛' => '┗',
+            '┗' => '┛',
+            c => *c,
+        };
+        assert_eq!(Symmetry::new().orientations(&g).len(), 1);
+        let turns = Symmetry::new().rotations(rotate).orientations(&g);
+        let turns: Vec<String> = turns.iter().map(text).collect();
+        assert_eq!(turns, vec!["┏━\n┃x", "━┓\nx┃", "x┃\n━┛", "┃x\n┗━"]);
+        let all = Symmetry::new()
+            .rotations(rotate)
+            .reflections(mirror)
+            .orientations(&g);
+        assert_eq!(all.len(), 8);
+        assert_eq!(text(&all[4]), "━┓\nx┃");
+
+        // Only a top-left corner was drawn, but every corner
+        // is learned.
+        let mut m = GridModel::new(Neighborhood::VonNeumann).border(' ');
+        m.train_symmetric(&chars("┏━\n┃ "), &Symmetry::new().rotations(rotate));
+        let view = |up, left, right, down| vec![Some(up), Some(left), Some(right), Some(down)];
+        assert_eq!(m.chain().argmax(&view(' ', '━', ' ', '┃')), Some('┓'));
+        assert_eq!(m.chain().argmax(&view('┃', '━', ' ', ' ')), Some('┛'));
+
+        // Views are worked out again in every orientation, so
+        // any square neighborhood works, even one that doesn't
+        // turn onto itself.
+        let corner = Neighborhood::Custom(vec![(-1, -1), (0, -1), (-1, 0)]);
+        let mut m = GridModel::new(corner);
+        m.train_symmetric(&chars("┏━\n┃ "), &Symmetry::new().rotations(rotate));
+        assert_eq!(
+            m.chain().argmax(&[Some(' '), Some('┃'), Some('━')]),
+            Some('┛')
+        );
+        let mut hex = GridModel::new(Neighborhood::Hex).border('.');
+        hex.train_symmetric(&chars("ab\nba"), &Symmetry::new());
+        assert_eq!(hex.chain().contexts().count(), 4);
+    }
+
+    #[test]
+    fn refines_tilemaps() {
+        let mut m = GridModel::new(Neighborhood::VonNeumann).border(' ');
+        m.train(&chars("       \n ┏━━┓  \n ┃  ┃  \n ┗━━┛  \n       "));
+        let options = GenerateOptions::new().seed(4);
+        let refined = m.refine(&Grid::new(8, 8), 200, &options);
+        assert!(refined.is_consistent(), "{:?}", refined.conflicts());
+        let grid = refined.into_cells();
+        assert!(grid.is_complete());
+        assert!(m.conflicts(&grid).is_empty());
+
+        let mut broken = grid.clone();
+        broken.set(0, 0, Some('┛'));
+        assert_eq!(m.conflicts(&broken), vec![(0, 0)]);
+
+        // There's no room for a box below and to the right.
+        let mut seeded = Grid::new(8, 8);
+        seeded.set(6, 6, Some('┏'));
+        let stuck = m.refine(&seeded, 50, &options);
+        assert_eq!(stuck.sweeps(), 50);
+        assert!(stuck.conflicts().contains(&(6, 6)));
+        assert_eq!(stuck.conflicts(), &m.conflicts(stuck.cells())[..]);
+        assert_eq!(stuck.cells().get(6, 6), Some(&'┏'));
+    }
+}
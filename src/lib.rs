@@ -1,6 +1,35 @@
+pub mod binary;
+pub mod codec;
+pub mod constraint;
+pub mod csv;
 mod die;
+mod divergence;
+pub mod dot;
+mod frozen;
+pub mod graph;
+pub mod grid;
+pub mod mapped;
+pub mod pnm;
+mod rng;
+mod storage;
+#[cfg(feature = "text")]
+pub mod text;
+pub mod texture;
+pub mod topology;
+mod trie;
+mod vocabulary;
+mod vom;
+mod wildcard;
+pub mod world;
 use cfg_if::cfg_if;
-use std::collections::HashMap;
+use std::borrow::Cow;
+
+pub use die::WeightedDie;
+pub use frozen::FrozenMarkovChain;
+pub use storage::Storage;
+pub use vocabulary::{InternedChain, Vocabulary};
+pub use vom::VariableOrderChain;
+pub use wildcard::Indexing;
 
 #[cfg(feature = "serializer")]
 extern crate serde;
@@ -11,46 +40,38 @@ use serde::{Deserialize, Serialize};
 
 // Element without serialization or debug
 #[cfg(all(not(feature = "serializer"), not(feature = "debug")))]
-pub trait Element: Eq + PartialEq + Copy + Clone + std::hash::Hash {}
+pub trait Element: Eq + PartialEq + Clone + std::hash::Hash {}
 #[cfg(all(not(feature = "serializer"), not(feature = "debug")))]
-impl<T> Element for T where T: Eq + PartialEq + Copy + Clone + std::hash::Hash {}
+impl<T> Element for T where T: Eq + PartialEq + Clone + std::hash::Hash {}
 
 // Element with serialization and no debug
 #[cfg(all(feature = "serializer", not(feature = "debug")))]
 pub trait Element:
-    Eq + PartialEq + Copy + Clone + std::hash::Hash + serde::Serialize + DeserializeOwned
+    Eq + PartialEq + Clone + std::hash::Hash + serde::Serialize + DeserializeOwned
 {
 }
 #[cfg(all(feature = "serializer", not(feature = "debug")))]
 impl<T> Element for T where
-    T: Eq + PartialEq + Copy + Clone + std::hash::Hash + serde::Serialize + DeserializeOwned
+    T: Eq + PartialEq + Clone + std::hash::Hash + serde::Serialize + DeserializeOwned
 {
 }
 
 // Element with debug and no serialization
 #[cfg(all(not(feature = "serializer"), feature = "debug"))]
-pub trait Element: Eq + PartialEq + Copy + Clone + std::hash::Hash + std::fmt::Debug {}
+pub trait Element: Eq + PartialEq + Clone + std::hash::Hash + std::fmt::Debug {}
 #[cfg(all(not(feature = "serializer"), feature = "debug"))]
-impl<T> Element for T where T: Eq + PartialEq + Copy + Clone + std::hash::Hash + std::fmt::Debug {}
+impl<T> Element for T where T: Eq + PartialEq + Clone + std::hash::Hash + std::fmt::Debug {}
 
 // Element with both serialization and debug
 #[cfg(all(feature = "serializer", feature = "debug"))]
 pub trait Element:
-    Eq
-    + PartialEq
-    + Copy
-    + Clone
-    + std::hash::Hash
-    + serde::Serialize
-    + DeserializeOwned
-    + std::fmt::Debug
+    Eq + PartialEq + Clone + std::hash::Hash + serde::Serialize + DeserializeOwned + std::fmt::Debug
 {
 }
 #[cfg(all(feature = "serializer", feature = "debug"))]
 impl<T> Element for T where
     T: Eq
         + PartialEq
-        + Copy
         + Clone
         + std::hash::Hash
         + serde::Serialize
@@ -62,13 +83,26 @@ impl<T> Element for T where
 #[cfg(all(feature = "serializer", feature = "debug", test))]
 mod serialization_tests;
 
-/// Variable-order Markov chain.
-#[derive(Clone, Eq, PartialEq)]
+/// Higher-order Markov chain, with a fixed order.
+/// See VariableOrderChain for a chain that picks how far
+/// back to look for each prediction.
+///
+/// Two chains are equal when they have the same shape and
+/// the same weights for every context, regardless of the
+/// order they were trained in or how they're indexed.
+///
+/// Contexts and die sides are serialized in a fixed order, by
+/// the bytes they feed to Hash, so equal chains serialize to
+/// the same output on every platform. Those bytes could change
+/// with the Rust release, which reorders the output but never
+/// stops it loading. Human-readable formats, like RON, also
+/// load chains saved by 0.5.
+#[derive(Clone)]
 #[cfg_attr(feature = "serializer", derive(Debug))]
 #[cfg_attr(feature = "serializer", derive(Serialize, Deserialize))]
 #[cfg_attr(
     feature = "serializer",
-    serde(bound = "T: Serialize, for<'t> T: Deserialize<'t>")
+    serde(bound(serialize = "T: Serialize", deserialize = "for<'t> T: Deserialize<'t>"))
 )]
 pub struct MarkovChain<T: Element> {
     // the 'memory' for the MarkovChain chain.
@@ -79,10 +113,42 @@ pub struct MarkovChain<T: Element> {
     // the number of elements in the key should
     // exactly equal the order of the MarkovChain chain.
     // missing elements should be represented as None.
-    probability_map: HashMap<Vec<Option<T>>, die::WeightedDie<T>>,
+    probability_map: storage::ContextMap<T>,
     optional_elements: Vec<usize>,
+    #[cfg_attr(feature = "serializer", serde(default))]
+    indexing: Indexing,
+    // aggregated dies for partial views, with Indexing::Wildcard.
+    #[cfg_attr(feature = "serializer", serde(skip))]
+    cache: wildcard::QueryCache<T>,
+}
+
+impl<T: Element> PartialEq for MarkovChain<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.order != other.order || self.optional_elements != other.optional_elements {
+            return false;
+        }
+        // two Wildcard chains only need their full contexts
+        // compared, since the rest are worked out from them.
+        let (mine, theirs) = match (self.indexing, other.indexing) {
+            (Indexing::Wildcard, Indexing::Wildcard) => (
+                Cow::Borrowed(&self.probability_map),
+                Cow::Borrowed(&other.probability_map),
+            ),
+            _ => (self.expanded_map(), other.expanded_map()),
+        };
+        // contexts that never got any weight don't change
+        // the distribution, so they are skipped.
+        let live = |m: &storage::ContextMap<T>| m.values().filter(|d| d.total_weight() > 0).count();
+        live(&mine) == live(&theirs)
+            && mine
+                .iter()
+                .filter(|(_, d)| d.total_weight() > 0)
+                .all(|(k, d)| theirs.get(&k) == Some(d))
+    }
 }
 
+impl<T: Element> Eq for MarkovChain<T> {}
+
 impl<T: Element> MarkovChain<T> {
     /// Creates a new MarkovChain.
     ///
@@ -101,25 +167,38 @@ impl<T: Element> MarkovChain<T> {
     /// 'optional_keys' allows you to specify None for
     /// the elements in the given indices during
     /// generation. This drastically increases the memory
-    /// usage by 2^optional_elements.len().
+    /// usage by 2^optional_elements.len(), unless the chain
+    /// is switched to Indexing::Wildcard.
     pub fn new(order: usize, optional_elements: &[usize]) -> Self {
-        // filter out optional elements that are too big.
-        let opts: Vec<usize> = optional_elements
-            .clone()
-            .into_iter()
-            .map(|i| *i)
+        Self::new_with_storage(order, optional_elements, Storage::default())
+    }
+
+    /// Creates a new MarkovChain that keeps its contexts
+    /// in the given kind of storage.
+    ///
+    /// See new for the other parameters.
+    pub fn new_with_storage(order: usize, optional_elements: &[usize], storage: Storage) -> Self {
+        // filter out optional elements that are too big,
+        // and keep them sorted so equal chains compare equal.
+        let mut opts: Vec<usize> = optional_elements
+            .iter()
+            .copied()
             .filter(|i| *i < order)
             .collect();
+        opts.sort_unstable();
+        opts.dedup();
         MarkovChain {
             order,
-            probability_map: HashMap::<Vec<Option<T>>, die::WeightedDie<T>>::new(),
+            probability_map: storage::ContextMap::new(storage),
             optional_elements: opts,
+            indexing: Indexing::default(),
+            cache: wildcard::QueryCache::default(),
         }
     }
 
     /// Truncates elements as needed
     fn to_partial_key(order: usize, view: &[Option<T>]) -> Vec<Option<T>> {
-        view.into_iter()
+        view.iter()
             .skip(view.len() - order)
             .take(order)
             .cloned()
@@ -128,7 +207,7 @@ impl<T: Element> MarkovChain<T> {
 
     /// Truncates elements as needed
     fn to_full_key(order: usize, view: &[T]) -> Vec<Option<T>> {
-        view.into_iter()
+        view.iter()
             .skip(view.len() - order)
             .take(order)
             .cloned()
@@ -141,7 +220,7 @@ impl<T: Element> MarkovChain<T> {
         optionals: Vec<usize>,
         mut perms: Vec<Vec<Option<T>>>,
     ) -> Vec<Vec<Option<T>>> {
-        if optionals.len() == 0 {
+        if optionals.is_empty() {
             perms.push(key);
             perms
         } else {
@@ -155,11 +234,43 @@ impl<T: Element> MarkovChain<T> {
         }
     }
 
-    // this generates 2^(number of optional keys) keys.
+    // this generates 2^(number of optional keys) keys,
+    // or just the full key with Indexing::Wildcard.
     // this is used during training.
     fn permute_key(&mut self, key: Vec<T>) -> Vec<Vec<Option<T>>> {
         let optioned_key: Vec<Option<T>> = key.into_iter().map(|e| Some(e)).collect();
-        Self::permute(optioned_key, self.optional_elements.clone(), vec![])
+        let optionals = match self.indexing {
+            Indexing::Expanded => self.optional_elements.clone(),
+            Indexing::Wildcard => vec![],
+        };
+        Self::permute(optioned_key, optionals, vec![])
+    }
+
+    /// Calls 'f' with the die for 'key', if there is one.
+    fn with_die<R>(&self, key: &[Option<T>], f: impl FnOnce(&WeightedDie<T>) -> R) -> Option<R> {
+        if self.indexing == Indexing::Wildcard && key.iter().any(|e| e.is_none()) {
+            // like Expanded, only optional elements can be None.
+            let optional = |i: &usize| self.optional_elements.contains(i);
+            if (0..key.len()).any(|i| key[i].is_none() && !optional(&i)) {
+                return None;
+            }
+            return self
+                .cache
+                .get_or_insert_with(key, || wildcard::aggregate(&self.probability_map, key))
+                .map(|die| f(&die));
+        }
+        self.probability_map.get(key).map(f)
+    }
+
+    /// Returns the contexts an Expanded chain would have.
+    pub(crate) fn expanded_map(&self) -> Cow<'_, storage::ContextMap<T>> {
+        match self.indexing {
+            Indexing::Expanded => Cow::Borrowed(&self.probability_map),
+            Indexing::Wildcard => Cow::Owned(wildcard::expand(
+                &self.probability_map,
+                &self.optional_elements,
+            )),
+        }
     }
 
     /// Feeds training data into the model.
@@ -174,19 +285,29 @@ impl<T: Element> MarkovChain<T> {
     /// loading this view into the model (typically 1 at
     /// a time).
     pub fn train(&mut self, view: &[T], result: T, weight_delta: i32) {
-        for partial_key in self.permute_key(view.clone().to_vec()) {
+        self.cache.clear();
+        for partial_key in self.permute_key(view.to_vec()) {
             // Train not just on the full key, but all partial ones as well.
             self.probability_map
-                .entry(partial_key)
-                .and_modify(|d| {
-                    d.modify(result, weight_delta);
-                })
-                .or_insert((|| {
-                    let mut d = die::WeightedDie::new();
-                    d.modify(result, weight_delta);
-                    d
-                })());
+                .get_or_insert(partial_key)
+                .modify(result.clone(), weight_delta);
+        }
+    }
+
+    /// Same as train, but adds an exact u64 weight.
+    /// Used when loading counts that were exported earlier.
+    ///
+    /// Returns None if the total weight of a context would
+    /// overflow a u64. Contexts trained before that keep the
+    /// new weight.
+    pub(crate) fn train_weight(&mut self, view: &[T], result: T, weight: u64) -> Option<()> {
+        self.cache.clear();
+        for partial_key in self.permute_key(view.to_vec()) {
+            self.probability_map
+                .get_or_insert(partial_key)
+                .checked_add_weight(result.clone(), weight)?;
         }
+        Some(())
     }
 
     /// Generates the next value, given the previous item(s).
@@ -202,10 +323,7 @@ impl<T: Element> MarkovChain<T> {
     ) -> Option<T> {
         let key = MarkovChain::to_partial_key(self.order, view);
 
-        match self.probability_map.get(&key) {
-            Some(v) => v.roll(Some(rand_val)),
-            None => None,
-        }
+        self.with_die(&key, |v| v.roll(Some(rand_val))).flatten()
     }
 
     /// Generates the next value, given the previous item(s).
@@ -217,10 +335,7 @@ impl<T: Element> MarkovChain<T> {
     pub fn generate_deterministic(&self, view: &[T], rand_val: u64) -> Option<T> {
         let key = MarkovChain::to_full_key(self.order, view);
 
-        match self.probability_map.get(&key) {
-            Some(v) => v.roll(Some(rand_val)),
-            None => None,
-        }
+        self.with_die(&key, |v| v.roll(Some(rand_val))).flatten()
     }
 
     cfg_if! {
@@ -232,10 +347,7 @@ impl<T: Element> MarkovChain<T> {
             pub fn generate(&self, view: &[T]) -> Option<T> {
                 let key = MarkovChain::to_full_key(self.order, view);
 
-                match self.probability_map.get(&key) {
-                    Some(v) => v.roll(None),
-                    None => None,
-                }
+                self.with_die(&key, |v| v.roll(None)).flatten()
             }
 
             /// Generates the next value, given the previous item(s).
@@ -245,25 +357,130 @@ impl<T: Element> MarkovChain<T> {
             pub fn generate_from_partial(&self, view: &[Option<T>]) -> Option<T> {
                 let key = MarkovChain::to_partial_key(self.order, view);
 
-                match self.probability_map.get(&key) {
-                    Some(v) => v.roll(None),
-                    None => None,
-                }
+                self.with_die(&key, |v| v.roll(None)).flatten()
             }
         }
     }
 
     /// Returns the probability of getting 'result', given
     /// 'view'.
-    pub fn probability(&self, view: &[Option<T>], result: T) -> f32 {
+    pub fn probability(&self, view: &[Option<T>], result: &T) -> f32 {
         let key = MarkovChain::to_partial_key(self.order, view);
 
-        let map = self.probability_map.get_key_value(&key);
-        match map {
-            Some(v) => v.1.get_probability(result),
-            None => 0.0,
+        self.with_die(&key, |v| v.get_probability(result))
+            .unwrap_or(0.0)
+    }
+
+    /// Returns the kind of storage this chain keeps its
+    /// contexts in.
+    pub fn storage(&self) -> Storage {
+        self.probability_map.storage()
+    }
+
+    /// Moves every context into a different kind of storage.
+    ///
+    /// Chains always deserialize with Storage::HashMap, so
+    /// call this after loading a chain that should use
+    /// something else.
+    pub fn set_storage(&mut self, storage: Storage) {
+        if storage != self.storage() {
+            self.probability_map = self.probability_map.convert(storage);
         }
     }
+
+    /// Returns how this chain answers views with unknown
+    /// elements.
+    pub fn indexing(&self) -> Indexing {
+        self.indexing
+    }
+
+    /// Changes how this chain answers views with unknown
+    /// elements. This is cheapest to call right after new,
+    /// before any training.
+    ///
+    /// Switching to Indexing::Wildcard drops every partial
+    /// context, keeping only the full ones. Switching back
+    /// works the partial contexts out again.
+    pub fn set_indexing(&mut self, indexing: Indexing) {
+        if indexing == self.indexing {
+            return;
+        }
+        self.probability_map = match indexing {
+            Indexing::Expanded => wildcard::expand(&self.probability_map, &self.optional_elements),
+            Indexing::Wildcard => {
+                let mut full = storage::ContextMap::new(self.storage());
+                for (key, die) in self
+                    .probability_map
+                    .iter()
+                    .filter(|(k, _)| k.iter().all(|e| e.is_some()))
+                {
+                    *full.get_or_insert(key) = die.clone();
+                }
+                full
+            }
+        };
+        self.indexing = indexing;
+        self.cache.clear();
+    }
+
+    /// Iterates over every context that has been trained,
+    /// in no particular order. Contexts whose weights were
+    /// all trained away are skipped.
+    pub fn contexts(&self) -> impl Iterator<Item = Vec<Option<T>>> + '_ {
+        self.probability_map
+            .iter()
+            .filter(|(_, d)| d.total_weight() > 0)
+            .map(|(k, _)| k)
+    }
+
+    /// Returns every outcome that can follow 'view', with its
+    /// weight, in the order rolls count them.
+    ///
+    /// For a full view, that's the order they were first
+    /// trained, until the chain is serialized: a loaded chain
+    /// keeps them in a canonical order instead. Otherwise the
+    /// order is unspecified, but the same every time.
+    pub fn distribution(&self, view: &[Option<T>]) -> Vec<(T, u64)> {
+        let key = MarkovChain::to_partial_key(self.order, view);
+        self.with_die(&key, |d| d.iter().filter(|s| s.1 > 0).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns every outcome that can follow 'view', in the
+    /// same order as distribution.
+    pub fn outcomes(&self, view: &[Option<T>]) -> Vec<T> {
+        self.distribution(view).into_iter().map(|s| s.0).collect()
+    }
+
+    /// Returns the most likely outcome after 'view'. Ties go
+    /// to the outcome that comes first in distribution.
+    pub fn argmax(&self, view: &[Option<T>]) -> Option<T> {
+        self.top_n(view, 1).pop().map(|s| s.0)
+    }
+
+    /// Returns the 'n' most likely outcomes after 'view',
+    /// with their weights, most likely first. Ties go to
+    /// the outcome that comes first in distribution.
+    pub fn top_n(&self, view: &[Option<T>], n: usize) -> Vec<(T, u64)> {
+        let mut sides = self.distribution(view);
+        sides.sort_by_key(|s| std::cmp::Reverse(s.1));
+        sides.truncate(n);
+        sides
+    }
+
+    /// Returns every trained context that ends with 'suffix'.
+    ///
+    /// This is how to find all of the longer histories that
+    /// agree with a short one. It's a walk over part of the
+    /// trie with Storage::Trie, and a scan over every context
+    /// otherwise.
+    pub fn extensions(&self, suffix: &[Option<T>]) -> Vec<Vec<Option<T>>> {
+        self.probability_map
+            .extensions(suffix)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -334,7 +551,7 @@ mod tests {
         }
 
         for i in 1..(encoded.len() - 1) {
-            let next = m.generate(&[encoded[i - 1], encoded[i].clone()]);
+            let next = m.generate(&[encoded[i - 1], encoded[i]]);
             match next {
                 Some(v) => assert_eq!(v, encoded[i + 1]),
                 None => panic!(
@@ -344,4 +561,156 @@ mod tests {
             };
         }
     }
+
+    #[test]
+    fn trie_storage() {
+        let words = ["january", "february", "march", "april", "may", "june"];
+        let mut hash = MarkovChain::new(3, &[0, 1]);
+        let mut trie = MarkovChain::new_with_storage(3, &[0, 1], Storage::Trie);
+        for word in words.iter() {
+            let w: Vec<char> = format!("$$${}.", word).chars().collect();
+            for i in 3..w.len() {
+                hash.train(&w[i - 3..i], w[i], 1);
+                trie.train(&w[i - 3..i], w[i], 1);
+            }
+        }
+        assert_eq!(trie.storage(), Storage::Trie);
+        assert!(hash == trie);
+        for roll in 0..8 {
+            assert_eq!(
+                hash.generate_deterministic_from_partial(&[None, Some('a'), Some('r')], roll),
+                trie.generate_deterministic_from_partial(&[None, Some('a'), Some('r')], roll)
+            );
+        }
+
+        let mut found = trie.extensions(&[Some('r')]);
+        let mut expected = hash.extensions(&[Some('r')]);
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert!(found.contains(&vec![Some('m'), Some('a'), Some('r')]));
+        assert!(found.contains(&vec![None, None, Some('r')]));
+
+        trie.set_storage(Storage::HashMap);
+        assert_eq!(trie.storage(), Storage::HashMap);
+        assert!(hash == trie);
+    }
+
+    #[test]
+    fn wildcard_indexing() {
+        let words = ["january", "february", "march", "april", "may", "june"];
+        let mut expanded = MarkovChain::new(3, &[0, 2]);
+        let mut wild = MarkovChain::new(3, &[0, 2]);
+        wild.set_indexing(Indexing::Wildcard);
+        for word in words.iter() {
+            let w: Vec<char> = format!("$$${}.", word).chars().collect();
+            for i in 3..w.len() {
+                expanded.train(&w[i - 3..i], w[i], 1);
+                wild.train(&w[i - 3..i], w[i], 1);
+            }
+        }
+        assert_eq!(wild.indexing(), Indexing::Wildcard);
+        assert!(wild
+            .extensions(&[])
+            .iter()
+            .all(|k| k.iter().all(|e| e.is_some())));
+
+        let views = [
+            [None, Some('a'), None],
+            [Some('m'), Some('a'), None],
+            [None, Some('u'), Some('n')],
+        ];
+        for view in views.iter() {
+            for e in "aeilnrsuy.".chars() {
+                assert_eq!(wild.probability(view, &e), expanded.probability(view, &e));
+            }
+        }
+        // Only optional elements can be wildcards.
+        assert_eq!(expanded.probability(&[Some('j'), None, None], &'u'), 0.0);
+        assert_eq!(wild.probability(&[Some('j'), None, None], &'u'), 0.0);
+        assert_eq!(wild.distribution(&[None, None, Some('r')]), vec![]);
+
+        // Training again clears cached aggregates.
+        assert_eq!(wild.probability(&[None, Some('b'), Some('z')], &'q'), 0.0);
+        wild.train(&['a', 'b', 'z'], 'q', 1);
+        assert_eq!(wild.probability(&[None, Some('b'), Some('z')], &'q'), 1.0);
+        expanded.train(&['a', 'b', 'z'], 'q', 1);
+
+        // Frozen chains work the partial contexts out up front,
+        // and roll the same way as the aggregates.
+        let frozen = wild.freeze();
+        for view in views.iter() {
+            for roll in 0..16 {
+                assert_eq!(
+                    frozen.generate_deterministic_from_partial(view, roll),
+                    wild.generate_deterministic_from_partial(view, roll)
+                );
+            }
+        }
+
+        wild.set_indexing(Indexing::Expanded);
+        assert!(wild == expanded);
+        wild.set_indexing(Indexing::Wildcard);
+        expanded.set_indexing(Indexing::Wildcard);
+        assert!(wild == expanded);
+    }
+
+    #[test]
+    fn clone_elements() {
+        let words: Vec<String> = "a rose is a rose is a rose"
+            .split(' ')
+            .map(String::from)
+            .collect();
+        let mut m = MarkovChain::new(1, &[0]);
+        for i in 1..words.len() {
+            m.train(&words[i - 1..i], words[i].clone(), 1);
+        }
+        let rose = "rose".to_string();
+        assert_eq!(
+            m.generate_deterministic(&["a".to_string()], 0),
+            Some(rose.clone())
+        );
+        assert_eq!(m.probability(&[Some(rose.clone())], &"is".to_string()), 1.0);
+        assert_eq!(m.probability(&[None], &rose), 3.0 / 7.0);
+
+        let mut m = MarkovChain::new_with_storage(2, &[], Storage::Trie);
+        m.train(&["x".to_string(), "y".to_string()], "z".to_string(), 1);
+        assert_eq!(
+            m.generate_deterministic(&["x".to_string(), "y".to_string()], 0),
+            Some("z".to_string())
+        );
+        assert_eq!(
+            m.freeze()
+                .generate_deterministic(&["x".to_string(), "y".to_string()], 5),
+            Some("z".to_string())
+        );
+    }
+
+    #[test]
+    fn introspection() {
+        let mut m = MarkovChain::new(1, &[0]);
+        for (view, result, weight) in
+            [('a', 'b', 1), ('a', 'c', 3), ('a', 'd', 3), ('b', 'a', 2)].iter()
+        {
+            m.train(&[*view], *result, *weight);
+        }
+        m.train(&['z'], 'z', 1);
+        m.train(&['z'], 'z', -1);
+
+        let mut contexts: Vec<Vec<Option<char>>> = m.contexts().collect();
+        contexts.sort();
+        assert_eq!(contexts, vec![vec![None], vec![Some('a')], vec![Some('b')]]);
+
+        let a = [Some('a')];
+        assert_eq!(m.distribution(&a), vec![('b', 1), ('c', 3), ('d', 3)]);
+        assert_eq!(m.outcomes(&a), vec!['b', 'c', 'd']);
+        assert_eq!(m.argmax(&a), Some('c'));
+        assert_eq!(m.top_n(&a, 2), vec![('c', 3), ('d', 3)]);
+        assert_eq!(m.top_n(&[None], 10).len(), 4);
+        assert_eq!(m.argmax(&[None]), Some('c'));
+
+        assert!(m.distribution(&[Some('q')]).is_empty());
+        assert_eq!(m.argmax(&[Some('z')]), None);
+        assert!(m.top_n(&a, 0).is_empty());
+    }
 }
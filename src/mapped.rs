@@ -0,0 +1,638 @@
+//! Zero-copy frozen models that are queried in place.
+//!
+//! FrozenMarkovChain::write_mapped writes a layout made of
+//! fixed-size records, so a MappedMarkovChain can answer
+//! generate and probability calls straight from the bytes of
+//! a file without deserializing it first. The bytes usually
+//! come from a memory-mapped file; with the 'mmap' feature,
+//! MappedFile does the mapping for you.
+//!
+//! All numbers are little-endian.
+//!
+//! ```text
+//! magic              4 bytes, "MKVM"
+//! version            u16
+//! element size       u16, bytes per element
+//! order              u32
+//! optional count     u32
+//! context count      u64
+//! side count         u64
+//! optional indices   u32 each
+//! hashes             u64 per context, ascending
+//! contexts           one record per context, in hash order
+//! offsets            u64 per context, plus one, into the sides
+//! items              element per side
+//! running weights    u64 per side, restarting at each context
+//! ```
+//!
+//! A context record is a known mask of ceil(order / 8) bytes
+//! (bit i, LSB first, is set when element i is known) followed
+//! by 'order' elements. Unknown elements are zero-filled.
+//! Each hash is the 64-bit FNV-1a hash of its context record.
+//!
+//! Only the header and section sizes are checked on load, so
+//! that loading stays fast for very large files. Queries check
+//! the offsets and weights they read, so a corrupt file can
+//! give wrong answers, but never panics.
+
+use cfg_if::cfg_if;
+use std::convert::TryInto;
+use std::hash::Hasher;
+use std::io::Write;
+
+use super::die::resolve_roll;
+use super::die::WeightedDie;
+use super::frozen::FnvHasher;
+use super::{Element, FrozenMarkovChain};
+
+const MAGIC: &[u8; 4] = b"MKVM";
+const HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 4 + 8 + 8;
+
+/// The newest layout version this build can read and write.
+pub const LAYOUT_VERSION: u16 = 1;
+
+/// Plain-old-data elements with a fixed-size encoding.
+pub trait PodElement: Element {
+    /// Number of bytes in the encoding.
+    const SIZE: usize;
+
+    /// Appends the little-endian encoding to 'out'.
+    fn write_le(&self, out: &mut Vec<u8>);
+
+    /// Decodes an element from exactly SIZE bytes.
+    /// Returns None if the bytes are not a valid element.
+    fn read_le(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! pod_int {
+    ($($t:ty),*) => {$(
+        impl PodElement for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn write_le(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read_le(bytes: &[u8]) -> Option<Self> {
+                Some(<$t>::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+    )*};
+}
+
+pod_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl PodElement for char {
+    const SIZE: usize = 4;
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(*self as u32).to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Option<Self> {
+        std::char::from_u32(u32::read_le(bytes)?)
+    }
+}
+
+/// Errors that can happen while opening a mapped model.
+#[derive(Debug)]
+pub enum MappedError {
+    /// The bytes don't start with the expected magic bytes.
+    BadMagic,
+    /// The bytes were written by a newer version of the layout.
+    UnsupportedVersion(u16),
+    /// The model was written for a different element type.
+    ElementSize { expected: usize, found: usize },
+    /// The bytes are shorter or longer than the header says.
+    BadLength,
+}
+
+impl std::fmt::Display for MappedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MappedError::BadMagic => write!(f, "not a mapped markovr model"),
+            MappedError::UnsupportedVersion(v) => write!(
+                f,
+                "mapped model version {} is newer than supported version {}",
+                v, LAYOUT_VERSION
+            ),
+            MappedError::ElementSize { expected, found } => write!(
+                f,
+                "mapped model has {}-byte elements, expected {}",
+                found, expected
+            ),
+            MappedError::BadLength => write!(f, "mapped model has the wrong length"),
+        }
+    }
+}
+
+impl std::error::Error for MappedError {}
+
+fn record_size<T: PodElement>(order: usize) -> usize {
+    order.div_ceil(8) + order * T::SIZE
+}
+
+/// Encodes a context the way it is stored in a record.
+fn encode_record<T: PodElement>(key: &[Option<T>], out: &mut Vec<u8>) {
+    let mut mask = vec![0u8; key.len().div_ceil(8)];
+    for (i, e) in key.iter().enumerate() {
+        if e.is_some() {
+            mask[i / 8] |= 1 << (i % 8);
+        }
+    }
+    out.extend_from_slice(&mask);
+    for e in key.iter() {
+        match e {
+            Some(v) => v.write_le(out),
+            None => out.extend(std::iter::repeat_n(0, T::SIZE)),
+        }
+    }
+}
+
+fn hash_record(record: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(record);
+    hasher.finish()
+}
+
+impl<T: PodElement> FrozenMarkovChain<T> {
+    /// Writes this chain in the zero-copy layout read by
+    /// MappedMarkovChain. See the module documentation.
+    pub fn write_mapped<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let order = self.order();
+        let size = record_size::<T>(order);
+
+        let mut records: Vec<(u64, Vec<u8>, usize)> = (0..self.len())
+            .map(|i| {
+                let mut record = Vec::with_capacity(size);
+                encode_record(self.context(i), &mut record);
+                (hash_record(&record), record, i)
+            })
+            .collect();
+        records.sort_unstable();
+
+        let sides: usize = (0..self.len()).map(|i| self.sides(i).0.len()).sum();
+        let mut out: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&LAYOUT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(T::SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(order as u32).to_le_bytes());
+        out.extend_from_slice(&(self.optional_elements().len() as u32).to_le_bytes());
+        out.extend_from_slice(&(records.len() as u64).to_le_bytes());
+        out.extend_from_slice(&(sides as u64).to_le_bytes());
+        for i in self.optional_elements() {
+            out.extend_from_slice(&(*i as u32).to_le_bytes());
+        }
+        for (hash, _, _) in records.iter() {
+            out.extend_from_slice(&hash.to_le_bytes());
+        }
+        for (_, record, _) in records.iter() {
+            out.extend_from_slice(record);
+        }
+        writer.write_all(&out)?;
+
+        // Sections are written one at a time, so that only
+        // one of them is buffered at once.
+        out.clear();
+        let mut offset: u64 = 0;
+        out.extend_from_slice(&offset.to_le_bytes());
+        for (_, _, i) in records.iter() {
+            offset += self.sides(*i).0.len() as u64;
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        writer.write_all(&out)?;
+
+        out.clear();
+        for (_, _, i) in records.iter() {
+            for e in self.sides(*i).0 {
+                e.write_le(&mut out);
+            }
+        }
+        writer.write_all(&out)?;
+
+        out.clear();
+        for (_, _, i) in records.iter() {
+            for w in self.sides(*i).1 {
+                out.extend_from_slice(&w.to_le_bytes());
+            }
+        }
+        writer.write_all(&out)
+    }
+}
+
+/// A frozen chain that reads straight from the bytes of
+/// the layout written by FrozenMarkovChain::write_mapped.
+///
+/// Lookups binary search the hash section and compare
+/// context records as raw bytes; nothing is copied out
+/// except the element that gets generated.
+#[derive(Clone, Copy)]
+pub struct MappedMarkovChain<'a, T: PodElement> {
+    bytes: &'a [u8],
+    order: usize,
+    contexts: usize,
+    sides: usize,
+    optional_start: usize,
+    hashes_start: usize,
+    records_start: usize,
+    offsets_start: usize,
+    items_start: usize,
+    running_start: usize,
+    _element: std::marker::PhantomData<T>,
+}
+
+impl<'a, T: PodElement> MappedMarkovChain<'a, T> {
+    /// Checks the header of 'bytes' and wraps them.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, MappedError> {
+        if bytes.len() < HEADER_SIZE {
+            return match bytes.starts_with(MAGIC) {
+                true => Err(MappedError::BadLength),
+                false => Err(MappedError::BadMagic),
+            };
+        }
+        if &bytes[..4] != MAGIC {
+            return Err(MappedError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > LAYOUT_VERSION {
+            return Err(MappedError::UnsupportedVersion(version));
+        }
+        let element_size = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        if element_size != T::SIZE {
+            return Err(MappedError::ElementSize {
+                expected: T::SIZE,
+                found: element_size,
+            });
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        let order = u32_at(8);
+        let optional = u32_at(12);
+        let contexts = u64_at(16);
+        let sides = u64_at(24);
+
+        // Section sizes are checked with overflow in mind, since
+        // the header could claim anything.
+        let sections = [
+            optional.checked_mul(4),
+            contexts.checked_mul(8),
+            contexts.checked_mul(record_size::<T>(order)),
+            contexts.checked_add(1).and_then(|c| c.checked_mul(8)),
+            sides.checked_mul(T::SIZE),
+            sides.checked_mul(8),
+        ];
+        let mut starts = [0usize; 7];
+        starts[0] = HEADER_SIZE;
+        for (i, size) in sections.iter().enumerate() {
+            starts[i + 1] = size
+                .and_then(|s| starts[i].checked_add(s))
+                .ok_or(MappedError::BadLength)?;
+        }
+        if starts[6] != bytes.len() {
+            return Err(MappedError::BadLength);
+        }
+
+        Ok(MappedMarkovChain {
+            bytes,
+            order,
+            contexts,
+            sides,
+            optional_start: starts[0],
+            hashes_start: starts[1],
+            records_start: starts[2],
+            offsets_start: starts[3],
+            items_start: starts[4],
+            running_start: starts[5],
+            _element: std::marker::PhantomData,
+        })
+    }
+
+    fn u64_at(&self, at: usize) -> u64 {
+        u64::from_le_bytes(self.bytes[at..at + 8].try_into().unwrap())
+    }
+
+    /// Returns the order of the chain this was frozen from.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Returns the optional elements of the chain this was
+    /// frozen from.
+    pub fn optional_elements(&self) -> Vec<usize> {
+        self.bytes[self.optional_start..self.hashes_start]
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()) as usize)
+            .collect()
+    }
+
+    /// Returns the number of contexts with at least one outcome.
+    pub fn len(&self) -> usize {
+        self.contexts
+    }
+
+    /// Returns true if no context has any outcomes.
+    pub fn is_empty(&self) -> bool {
+        self.contexts == 0
+    }
+
+    /// Finds the range of sides for 'key'. Ranges that run
+    /// backwards or past the sides are treated as missing.
+    fn find(&self, key: &[Option<T>]) -> Option<(usize, usize)> {
+        let key = &key[key.len() - self.order..];
+        let size = record_size::<T>(self.order);
+        let mut record = Vec::with_capacity(size);
+        encode_record(key, &mut record);
+        let hash = hash_record(&record);
+
+        let hash_of = |i: usize| self.u64_at(self.hashes_start + i * 8);
+        let (mut lo, mut hi) = (0, self.contexts);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if hash_of(mid) < hash {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let found = (lo..self.contexts)
+            .take_while(|i| hash_of(*i) == hash)
+            .find(|i| {
+                let at = self.records_start + i * size;
+                self.bytes[at..at + size] == record[..]
+            })?;
+        let start = self.u64_at(self.offsets_start + found * 8) as usize;
+        let end = self.u64_at(self.offsets_start + (found + 1) * 8) as usize;
+        match start <= end && end <= self.sides {
+            true => Some((start, end)),
+            false => None,
+        }
+    }
+
+    fn item(&self, i: usize) -> Option<T> {
+        let at = self.items_start + i * T::SIZE;
+        T::read_le(&self.bytes[at..at + T::SIZE])
+    }
+
+    fn running(&self, i: usize) -> u64 {
+        self.u64_at(self.running_start + i * 8)
+    }
+
+    fn roll(&self, key: &[Option<T>], roll: Option<u64>) -> Option<T> {
+        let (start, end) = self.find(key)?;
+        if start >= end || self.running(end - 1) == 0 {
+            return None;
+        }
+        let roll_result = resolve_roll(self.running(end - 1), roll);
+        let (mut lo, mut hi) = (start, end - 1);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.running(mid) <= roll_result {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        self.item(lo)
+    }
+
+    fn full_key(view: &[T]) -> Vec<Option<T>> {
+        view.iter().map(|e| Some(e.clone())).collect()
+    }
+
+    /// Generates the next value, given the previous item(s).
+    ///
+    /// view is the sliding window of the latest elements.
+    /// only the last self.order elements are looked at.
+    ///
+    /// rand_val allows for a deterministic result, if supplied.
+    pub fn generate_deterministic_from_partial(
+        &self,
+        view: &[Option<T>],
+        rand_val: u64,
+    ) -> Option<T> {
+        self.roll(view, Some(rand_val))
+    }
+
+    /// Generates the next value, given the previous item(s).
+    ///
+    /// view is the sliding window of the latest elements.
+    /// only the last self.order elements are looked at.
+    ///
+    /// rand_val allows for a deterministic result, if supplied.
+    pub fn generate_deterministic(&self, view: &[T], rand_val: u64) -> Option<T> {
+        self.roll(&Self::full_key(view), Some(rand_val))
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "rand")] {
+            /// Generates the next value, given the previous item(s).
+            ///
+            /// view is the sliding window of the latest elements.
+            /// only the last self.order elements are looked at.
+            pub fn generate(&self, view: &[T]) -> Option<T> {
+                self.roll(&Self::full_key(view), None)
+            }
+
+            /// Generates the next value, given the previous item(s).
+            ///
+            /// view is the sliding window of the latest elements.
+            /// only the last self.order elements are looked at.
+            pub fn generate_from_partial(&self, view: &[Option<T>]) -> Option<T> {
+                self.roll(view, None)
+            }
+        }
+    }
+
+    /// Returns the probability of getting 'result', given
+    /// 'view'.
+    pub fn probability(&self, view: &[Option<T>], result: &T) -> f32 {
+        let (start, end) = match self.find(view) {
+            Some(r) => r,
+            None => return 0.0,
+        };
+        match (start..end).find(|i| self.item(*i).as_ref() == Some(result)) {
+            Some(i) => {
+                let weight = match i == start {
+                    true => Some(self.running(i)),
+                    false => self.running(i).checked_sub(self.running(i - 1)),
+                };
+                let total = self.running(end - 1);
+                match weight {
+                    Some(weight) if weight <= total && total > 0 => {
+                        WeightedDie::<T>::less_lossy_divide(weight, total)
+                    }
+                    _ => 0.0,
+                }
+            }
+            None => 0.0,
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "mmap")] {
+        /// A memory-mapped model file.
+        pub struct MappedFile {
+            map: memmap2::Mmap,
+        }
+
+        impl MappedFile {
+            /// Maps the file at 'path' into memory.
+            ///
+            /// The file must not be modified or truncated while
+            /// it is mapped.
+            pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+                let file = std::fs::File::open(path)?;
+                // Safety: the map is read-only, and callers are told
+                // not to change the file while it is mapped.
+                let map = unsafe { memmap2::Mmap::map(&file)? };
+                Ok(MappedFile { map })
+            }
+
+            /// Returns a chain that reads from the mapped file.
+            pub fn chain<T: PodElement>(&self) -> Result<MappedMarkovChain<'_, T>, MappedError> {
+                MappedMarkovChain::new(&self.map)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarkovChain;
+
+    fn write<T: PodElement>(m: &MarkovChain<T>) -> Vec<u8> {
+        let mut out = vec![];
+        m.freeze().write_mapped(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn matches_chain() {
+        let mut m = MarkovChain::new(2, &[1]);
+        let text: Vec<char> = "the rain in spain falls mainly on the plain"
+            .chars()
+            .collect();
+        for i in 2..text.len() {
+            m.train(&[text[i - 2], text[i - 1]], text[i], 1);
+        }
+        let bytes = write(&m);
+        let mapped = MappedMarkovChain::<char>::new(&bytes).unwrap();
+        assert_eq!(mapped.order(), 2);
+        assert_eq!(mapped.optional_elements(), vec![1]);
+        assert_eq!(mapped.len(), m.probability_map.values().count());
+
+        for (key, die) in m.probability_map.iter() {
+            for roll in 0..die.total_weight() * 2 {
+                assert_eq!(
+                    mapped.generate_deterministic_from_partial(&key, roll),
+                    m.generate_deterministic_from_partial(&key, roll)
+                );
+            }
+            for (e, _) in die.iter() {
+                assert_eq!(mapped.probability(&key, &e), m.probability(&key, &e));
+            }
+        }
+        assert_eq!(
+            mapped.generate_deterministic(&['x', 'a', 'i'], 0),
+            Some('n')
+        );
+        assert_eq!(mapped.generate_deterministic(&['z', 'z'], 0), None);
+        assert_eq!(mapped.probability(&[Some('z'), None], &'a'), 0.0);
+    }
+
+    #[test]
+    fn integers() {
+        let mut m = MarkovChain::new(1, &[]);
+        m.train(&[300u16], 2, 1);
+        m.train(&[300u16], 65535, 3);
+        let bytes = write(&m);
+        let mapped = MappedMarkovChain::<u16>::new(&bytes).unwrap();
+        assert_eq!(mapped.generate_deterministic(&[300], 0), Some(2));
+        assert_eq!(mapped.generate_deterministic(&[300], 1), Some(65535));
+        assert_eq!(mapped.probability(&[Some(300)], &65535), 0.75);
+
+        let empty: MarkovChain<u8> = MarkovChain::new(3, &[]);
+        let bytes = write(&empty);
+        let mapped = MappedMarkovChain::<u8>::new(&bytes).unwrap();
+        assert!(mapped.is_empty());
+        assert_eq!(mapped.generate_deterministic(&[1, 2, 3], 0), None);
+    }
+
+    #[test]
+    fn rejects_bad_bytes() {
+        let mut m = MarkovChain::new(1, &[]);
+        m.train(&[1u32], 2, 1);
+        let bytes = write(&m);
+
+        assert!(matches!(
+            MappedMarkovChain::<u32>::new(b"nope"),
+            Err(MappedError::BadMagic)
+        ));
+        assert!(matches!(
+            MappedMarkovChain::<u16>::new(&bytes),
+            Err(MappedError::ElementSize {
+                expected: 2,
+                found: 4
+            })
+        ));
+        assert!(matches!(
+            MappedMarkovChain::<u32>::new(&bytes[..bytes.len() - 1]),
+            Err(MappedError::BadLength)
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4] = 9;
+        assert!(matches!(
+            MappedMarkovChain::<u32>::new(&newer),
+            Err(MappedError::UnsupportedVersion(9))
+        ));
+
+        let mut huge = bytes;
+        huge[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            MappedMarkovChain::<u32>::new(&huge),
+            Err(MappedError::BadLength)
+        ));
+    }
+
+    #[test]
+    fn corrupt_bytes() {
+        let mut m = MarkovChain::new(1, &[0]);
+        for (a, b) in [(1u16, 2), (2, 3), (3, 1), (1, 3)] {
+            m.train(&[a], b, 2);
+        }
+        let bytes = write(&m);
+
+        // Flip every bit past the header in turn. The header
+        // sizes still match, so every file loads, and queries
+        // must not panic however wrong their answers are.
+        for at in HEADER_SIZE..bytes.len() {
+            for bit in 0..8 {
+                let mut flipped = bytes.clone();
+                flipped[at] ^= 1 << bit;
+                let mapped = MappedMarkovChain::<u16>::new(&flipped).unwrap();
+                for a in 0..4 {
+                    for key in [[Some(a)], [None]] {
+                        for roll in 0..8 {
+                            mapped.generate_deterministic_from_partial(&key, roll);
+                        }
+                        for b in 0..4 {
+                            mapped.probability(&key, &b);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Offsets past the end of the sides.
+        let good = MappedMarkovChain::<u16>::new(&bytes).unwrap();
+        let offsets = good.offsets_start..good.items_start;
+        let mut flipped = bytes.clone();
+        flipped[offsets].fill(0xff);
+        let mapped = MappedMarkovChain::<u16>::new(&flipped).unwrap();
+        assert_eq!(mapped.probability(&[None], &1), 0.0);
+        assert_eq!(mapped.generate_deterministic(&[1], 0), None);
+    }
+}
@@ -0,0 +1,283 @@
+//! Reading and writing images in the netpbm formats: PGM for
+//! grayscale and PPM for color.
+//!
+//! Both the plain (P2, P3) and raw (P5, P6) variants can be
+//! read, with any maxval up to 255. Samples are scaled to the
+//! full 0..=255 range on the way in. Images are written raw,
+//! with a maxval of 255.
+
+use std::io::{Read, Write};
+
+use super::grid::Grid;
+
+/// Errors that can happen while reading an image.
+#[derive(Debug)]
+pub enum PnmError {
+    /// The underlying reader failed.
+    Io(std::io::Error),
+    /// The header is missing or malformed.
+    BadHeader(String),
+    /// The image is valid netpbm, but not a kind this module
+    /// reads, like 16-bit samples or bitmaps.
+    Unsupported(String),
+    /// The pixel data is malformed or ends early.
+    BadData(String),
+}
+
+impl std::fmt::Display for PnmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PnmError::Io(e) => write!(f, "io error: {}", e),
+            PnmError::BadHeader(message) => write!(f, "bad header: {}", message),
+            PnmError::Unsupported(message) => write!(f, "unsupported image: {}", message),
+            PnmError::BadData(message) => write!(f, "bad pixel data: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PnmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PnmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PnmError {
+    fn from(e: std::io::Error) -> Self {
+        PnmError::Io(e)
+    }
+}
+
+/// A decoded image: its size, and 1 or 3 samples per pixel,
+/// row by row.
+struct Raster {
+    width: usize,
+    height: usize,
+    channels: usize,
+    samples: Vec<u8>,
+}
+
+/// Walks the bytes of an image.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Skips whitespace and comments, which run from '#' to
+    /// the end of the line.
+    fn skip_blank(&mut self) {
+        while let Some(b) = self.bytes.get(self.pos) {
+            match b {
+                b'#' => {
+                    while self.bytes.get(self.pos).is_some_and(|b| *b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                b if b.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    /// Reads an unsigned decimal number.
+    fn number(&mut self) -> Option<usize> {
+        self.skip_blank();
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+}
+
+fn read_raster<R: Read>(mut reader: R) -> Result<Raster, PnmError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let (plain, channels) = match bytes.get(..2) {
+        Some(b"P2") => (true, 1),
+        Some(b"P3") => (true, 3),
+        Some(b"P5") => (false, 1),
+        Some(b"P6") => (false, 3),
+        Some(b"P1") | Some(b"P4") => {
+            return Err(PnmError::Unsupported("bitmaps (P1, P4)".into()));
+        }
+        _ => return Err(PnmError::BadHeader("not a PGM or PPM image".into())),
+    };
+
+    let mut c = Cursor {
+        bytes: &bytes,
+        pos: 2,
+    };
+    let mut field = |name: &str| {
+        c.number()
+            .ok_or_else(|| PnmError::BadHeader(format!("missing {}", name)))
+    };
+    let width = field("width")?;
+    let height = field("height")?;
+    let maxval = field("maxval")?;
+    if maxval == 0 || maxval > 255 {
+        return Err(PnmError::Unsupported(format!("maxval {}", maxval)));
+    }
+    let count = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or_else(|| PnmError::BadHeader("image is too large".into()))?;
+
+    let scale = |v: usize| -> Result<u8, PnmError> {
+        match v <= maxval {
+            true => Ok(((v * 255 + maxval / 2) / maxval) as u8),
+            false => Err(PnmError::BadData(format!(
+                "{} is over maxval {}",
+                v, maxval
+            ))),
+        }
+    };
+    let samples = if plain {
+        (0..count)
+            .map(|_| {
+                c.number()
+                    .ok_or_else(|| PnmError::BadData("too few samples".into()))
+                    .and_then(scale)
+            })
+            .collect::<Result<Vec<u8>, PnmError>>()?
+    } else {
+        // A single whitespace byte separates the header from
+        // the samples.
+        let start = c.pos + 1;
+        match bytes.get(start..start + count) {
+            Some(raw) => raw
+                .iter()
+                .map(|v| scale(*v as usize))
+                .collect::<Result<Vec<u8>, PnmError>>()?,
+            None => return Err(PnmError::BadData("too few samples".into())),
+        }
+    };
+    Ok(Raster {
+        width,
+        height,
+        channels,
+        samples,
+    })
+}
+
+fn to_grid<T: Clone>(raster: &Raster, pixel: impl Fn(&[u8]) -> T) -> Grid<T> {
+    let mut grid = Grid::new(raster.width, raster.height);
+    for (i, p) in raster.samples.chunks(raster.channels).enumerate() {
+        grid.set(i % raster.width, i / raster.width, Some(pixel(p)));
+    }
+    grid
+}
+
+/// Reads a grayscale image (P2 or P5).
+pub fn read_pgm<R: Read>(reader: R) -> Result<Grid<u8>, PnmError> {
+    let raster = read_raster(reader)?;
+    if raster.channels != 1 {
+        return Err(PnmError::Unsupported(
+            "color image, expected grayscale".into(),
+        ));
+    }
+    Ok(to_grid(&raster, |p| p[0]))
+}
+
+/// Reads a color image (P3 or P6) as [red, green, blue]
+/// pixels. Grayscale images are read too, with all three
+/// channels the same.
+pub fn read_ppm<R: Read>(reader: R) -> Result<Grid<[u8; 3]>, PnmError> {
+    let raster = read_raster(reader)?;
+    Ok(match raster.channels {
+        1 => to_grid(&raster, |p| [p[0]; 3]),
+        _ => to_grid(&raster, |p| [p[0], p[1], p[2]]),
+    })
+}
+
+fn write_raster<W: Write, T: Clone>(
+    mut writer: W,
+    magic: &str,
+    grid: &Grid<T>,
+    sample: impl Fn(Option<&T>, &mut Vec<u8>),
+) -> std::io::Result<()> {
+    write!(
+        writer,
+        "{}\n{} {}\n255\n",
+        magic,
+        grid.width(),
+        grid.height()
+    )?;
+    let mut bytes = vec![];
+    for row in grid.rows() {
+        for cell in row {
+            sample(cell.as_ref(), &mut bytes);
+        }
+    }
+    writer.write_all(&bytes)
+}
+
+/// Writes a grayscale image (P5). Empty cells are black.
+pub fn write_pgm<W: Write>(writer: W, grid: &Grid<u8>) -> std::io::Result<()> {
+    write_raster(writer, "P5", grid, |p, out| {
+        out.push(p.copied().unwrap_or(0))
+    })
+}
+
+/// Writes a color image (P6). Empty cells are black.
+pub fn write_ppm<W: Write>(writer: W, grid: &Grid<[u8; 3]>) -> std::io::Result<()> {
+    write_raster(writer, "P6", grid, |p, out| {
+        out.extend_from_slice(&p.copied().unwrap_or([0; 3]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut image = Grid::new(3, 2);
+        image.set(0, 0, Some([255, 0, 0]));
+        image.set(2, 1, Some([1, 2, 3]));
+        let mut bytes = vec![];
+        write_ppm(&mut bytes, &image).unwrap();
+        assert!(bytes.starts_with(b"P6\n3 2\n255\n"));
+        let back = read_ppm(&bytes[..]).unwrap();
+        assert_eq!(back.get(0, 0), Some(&[255, 0, 0]));
+        assert_eq!(back.get(1, 0), Some(&[0, 0, 0]));
+        assert_eq!(back.get(2, 1), Some(&[1, 2, 3]));
+
+        let gray = Grid::from_rows(&[[0u8, 128], [255, 7]]);
+        let mut bytes = vec![];
+        write_pgm(&mut bytes, &gray).unwrap();
+        assert_eq!(read_pgm(&bytes[..]).unwrap(), gray);
+        assert_eq!(read_ppm(&bytes[..]).unwrap().get(1, 0), Some(&[128; 3]));
+    }
+
+    #[test]
+    fn plain_formats() {
+        let text = "P2\n# a comment\n2 2 # trailing\n15\n0 15\n 5\n10\n";
+        let image = read_pgm(text.as_bytes()).unwrap();
+        assert_eq!(image, Grid::from_rows(&[[0u8, 255], [85, 170]]));
+
+        let text = "P3 1 1 255 10 20 30";
+        let image = read_ppm(text.as_bytes()).unwrap();
+        assert_eq!(image.get(0, 0), Some(&[10, 20, 30]));
+    }
+
+    #[test]
+    fn bad_images() {
+        let err = |text: &[u8]| read_ppm(text).unwrap_err();
+        assert!(matches!(err(b"JFIF"), PnmError::BadHeader(_)));
+        assert!(matches!(err(b"P6 2 2"), PnmError::BadHeader(_)));
+        assert!(matches!(err(b"P6 1 1 65535\n"), PnmError::Unsupported(_)));
+        assert!(matches!(err(b"P6 2 1 255\nabc"), PnmError::BadData(_)));
+        assert!(matches!(err(b"P2 1 1 9 10"), PnmError::BadData(_)));
+        assert!(matches!(
+            read_pgm(&b"P3 1 1 255 1 2 3"[..]),
+            Err(PnmError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+use cfg_if::cfg_if;
+
+/// SplitMix64. Small, fast, and gives the same stream on
+/// every platform, which keeps seeded generation repeatable.
+#[derive(Clone)]
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    /// Seeds from 'seed', or from the thread rng if None.
+    pub(crate) fn from_seed(seed: Option<u64>) -> Self {
+        match seed {
+            Some(s) => SplitMix64::new(s),
+            None => {
+                cfg_if! {
+                    if #[cfg(feature = "rand")] {
+                        SplitMix64::new(rand::random())
+                    } else {
+                        panic!("'seed' is not optional when the 'rand' feature is off.");
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.0)
+    }
+}
+
+/// The SplitMix64 finalizer. Scrambles every bit of 'z'
+/// into every bit of the result.
+pub(crate) fn mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
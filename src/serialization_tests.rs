@@ -1,4 +1,5 @@
 extern crate ron;
+extern crate serde_json;
 
 use crate::Element;
 use crate::MarkovChain;
@@ -16,6 +17,15 @@ where
     let b_de: MarkovChain<E> = ron::from_str(&b_s).unwrap();
 
     assert_eq!(a_de, b_de);
+
+    let a_json = serde_json::to_string(a).unwrap();
+    let b_json = serde_json::to_string(b).unwrap();
+
+    let a_json_de: MarkovChain<E> = serde_json::from_str(&a_json).unwrap();
+    let b_json_de: MarkovChain<E> = serde_json::from_str(&b_json).unwrap();
+
+    assert_eq!(a_json_de, b_json_de);
+    assert_eq!(a_json_de, *a);
 }
 
 #[test]
@@ -42,3 +52,112 @@ fn diff_train_order_eq() {
     }
     assert_chain_eq(&m1, &m2);
 }
+
+#[test]
+fn json_partial_keys() {
+    let mut m = MarkovChain::new(2, &[0]);
+    m.train(&['a', 'b'], 'c', 2);
+    m.train(&['b', 'c'], 'a', 1);
+
+    let json = serde_json::to_string(&m).unwrap();
+    let de: MarkovChain<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(de, m);
+    assert_eq!(de.probability(&[None, Some('b')], &'c'), 1.0);
+}
+
+#[test]
+fn diff_train_order_same_output() {
+    let mut m1 = MarkovChain::new(1, &[0]);
+    let mut m2 = MarkovChain::new(1, &[0]);
+    let text: Vec<char> = "the quick brown fox jumps over the lazy dog"
+        .chars()
+        .collect();
+    for i in 1..text.len() {
+        m1.train(&[text[i - 1]], text[i], 1);
+        let rev = text.len() - i;
+        m2.train(&[text[rev - 1]], text[rev], 1);
+    }
+    assert_chain_eq(&m1, &m2);
+    assert_eq!(ron::to_string(&m1).unwrap(), ron::to_string(&m2).unwrap());
+    assert_eq!(
+        serde_json::to_string(&m1).unwrap(),
+        serde_json::to_string(&m2).unwrap()
+    );
+}
+
+#[test]
+fn loads_old_models() {
+    // Written by markovr 0.5.
+    let old = "(order:2,probability_map:{\
+        [None,Some('b')]:(items:['c','d'],running_weight:[2,3]),\
+        [Some('a'),Some('b')]:(items:['c','d'],running_weight:[2,3])},\
+        optional_elements:[0])";
+    let mut m = MarkovChain::new(2, &[0]);
+    m.train(&['a', 'b'], 'c', 2);
+    m.train(&['a', 'b'], 'd', 1);
+    let de: MarkovChain<char> = ron::from_str(old).unwrap();
+    assert_eq!(de, m);
+    assert_eq!(de.probability(&[None, Some('b')], &'d'), 1.0 / 3.0);
+
+    let bad = "(order:1,probability_map:{[Some('a')]:(items:['b'],running_weight:[2,1])},\
+        optional_elements:[])";
+    assert!(ron::from_str::<MarkovChain<char>>(bad).is_err());
+}
+
+#[test]
+fn unordered_elements() {
+    // Tiles can be hashed, but not ordered.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq, serde::Serialize, serde::Deserialize)]
+    enum Tile {
+        Water,
+        Sand(u8),
+    }
+    let tiles = [Tile::Water, Tile::Sand(2), Tile::Sand(1), Tile::Water];
+    let mut m1 = MarkovChain::new(1, &[]);
+    let mut m2 = MarkovChain::new(1, &[]);
+    for i in 1..tiles.len() {
+        m1.train(&tiles[i - 1..i], tiles[i].clone(), 1);
+        let rev = tiles.len() - i;
+        m2.train(&tiles[rev - 1..rev], tiles[rev].clone(), 1);
+    }
+    assert_chain_eq(&m1, &m2);
+    assert_eq!(ron::to_string(&m1).unwrap(), ron::to_string(&m2).unwrap());
+}
+
+#[test]
+fn wildcard_indexing() {
+    let mut m = MarkovChain::new(2, &[0]);
+    m.set_indexing(crate::Indexing::Wildcard);
+    m.train(&['a', 'b'], 'c', 2);
+    m.train(&['x', 'b'], 'a', 1);
+    assert_chain_eq(&m, &m.clone());
+
+    let json = serde_json::to_string(&m).unwrap();
+    let de: MarkovChain<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(de.indexing(), crate::Indexing::Wildcard);
+    assert_eq!(de.probability(&[None, Some('b')], &'c'), 2.0 / 3.0);
+}
+
+#[test]
+fn interned_chain() {
+    let words: Vec<String> = "to be or not to be".split(' ').map(String::from).collect();
+    let mut m = crate::InternedChain::new(1, &[]);
+    for i in 1..words.len() {
+        m.train(&words[i - 1..i], &words[i], 1);
+    }
+
+    let json = serde_json::to_string(&m).unwrap();
+    let de: crate::InternedChain<String> = serde_json::from_str(&json).unwrap();
+    assert!(de == m);
+    assert_eq!(de.vocabulary().id("not"), m.vocabulary().id("not"));
+    assert_eq!(
+        de.generate_deterministic(&["not".to_string()], 0),
+        Some(&"to".to_string())
+    );
+
+    let ron = ron::to_string(&m).unwrap();
+    let de: crate::InternedChain<String> = ron::from_str(&ron).unwrap();
+    assert!(de == m);
+
+    assert!(serde_json::from_str::<crate::Vocabulary<String>>(r#"["a","b","a"]"#).is_err());
+}
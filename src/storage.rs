@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "serializer")]
+use serde::de::{MapAccess, SeqAccess, Visitor};
+#[cfg(feature = "serializer")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serializer")]
+use super::die::sort_key;
+use super::die::WeightedDie;
+use super::trie::ContextTrie;
+use super::Element;
+
+/// How a MarkovChain stores its contexts.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Storage {
+    /// Each context is its own key in a HashMap.
+    /// This is the default, and is the fastest to look up.
+    #[default]
+    HashMap,
+    /// Contexts share storage with every other context that
+    /// ends the same way, rather than each keeping a copy of
+    /// its whole key. Finding the contexts that end a given
+    /// way, as Indexing::Wildcard does, only visits those
+    /// contexts.
+    ///
+    /// This saves memory when contexts overlap a lot, like the
+    /// partial contexts of Indexing::Expanded. Long contexts
+    /// that rarely end the same way take more, since each of
+    /// their elements gets a node of its own. A trie holds up
+    /// to 2^32 - 1 nodes.
+    Trie,
+}
+
+/// A context and its die.
+pub(crate) type Entry<'a, T> = (Vec<Option<T>>, &'a WeightedDie<T>);
+
+/// The dies of a MarkovChain, keyed by context.
+#[derive(Clone)]
+pub(crate) enum ContextMap<T: Element> {
+    Hash(HashMap<Vec<Option<T>>, WeightedDie<T>>),
+    Trie(ContextTrie<T>),
+}
+
+impl<T: Element> ContextMap<T> {
+    pub(crate) fn new(storage: Storage) -> Self {
+        match storage {
+            Storage::HashMap => ContextMap::Hash(HashMap::new()),
+            Storage::Trie => ContextMap::Trie(ContextTrie::new()),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> Storage {
+        match self {
+            ContextMap::Hash(_) => Storage::HashMap,
+            ContextMap::Trie(_) => Storage::Trie,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &[Option<T>]) -> Option<&WeightedDie<T>> {
+        match self {
+            ContextMap::Hash(m) => m.get(key),
+            ContextMap::Trie(t) => t.get(key),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &[Option<T>]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the die for 'key', adding an empty one if needed.
+    pub(crate) fn get_or_insert(&mut self, key: Vec<Option<T>>) -> &mut WeightedDie<T> {
+        match self {
+            ContextMap::Hash(m) => m.entry(key).or_insert_with(WeightedDie::new),
+            ContextMap::Trie(t) => t.get_or_insert(&key),
+        }
+    }
+
+    /// Iterates over every context and its die, in no particular order.
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = Entry<'_, T>> + '_> {
+        match self {
+            ContextMap::Hash(m) => Box::new(m.iter().map(|(k, d)| (k.clone(), d))),
+            ContextMap::Trie(t) => Box::new(t.iter()),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = Vec<Option<T>>> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub(crate) fn values(&self) -> Box<dyn Iterator<Item = &WeightedDie<T>> + '_> {
+        match self {
+            ContextMap::Hash(m) => Box::new(m.values()),
+            ContextMap::Trie(t) => Box::new(t.values()),
+        }
+    }
+
+    /// Returns every context with a die that ends with 'suffix'.
+    pub(crate) fn extensions(&self, suffix: &[Option<T>]) -> Vec<Entry<'_, T>> {
+        match self {
+            ContextMap::Hash(m) => m
+                .iter()
+                .filter(|(k, _)| k.ends_with(suffix))
+                .map(|(k, d)| (k.clone(), d))
+                .collect(),
+            ContextMap::Trie(t) => t.extensions(suffix),
+        }
+    }
+
+    /// Copies every die into a new map with different storage.
+    pub(crate) fn convert(&self, storage: Storage) -> Self {
+        let mut converted = ContextMap::new(storage);
+        for (key, die) in self.iter() {
+            *converted.get_or_insert(key) = die.clone();
+        }
+        converted
+    }
+}
+
+impl<T: Element + std::fmt::Debug> std::fmt::Debug for ContextMap<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Serialized as a list of (context, die) entries, sorted by the
+// bytes each context hashes. Most self-describing formats, like JSON, only allow
+// string keys in maps, and sorting keeps the output from
+// depending on iteration order.
+#[cfg(feature = "serializer")]
+impl<T: Element> Serialize for ContextMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<Entry<'_, T>> = self.iter().collect();
+        entries.sort_by_cached_key(|e| sort_key(&e.0));
+        serializer.collect_seq(entries)
+    }
+}
+
+// Always deserialized into HashMap storage. Human-readable
+// formats also accept the map of contexts to dies that 0.5
+// wrote.
+#[cfg(feature = "serializer")]
+impl<'de, T: Element> Deserialize<'de> for ContextMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(MapVisitor(std::marker::PhantomData))
+        } else {
+            deserializer.deserialize_seq(MapVisitor(std::marker::PhantomData))
+        }
+    }
+}
+
+#[cfg(feature = "serializer")]
+struct MapVisitor<T>(std::marker::PhantomData<T>);
+
+#[cfg(feature = "serializer")]
+impl<'de, T: Element> Visitor<'de> for MapVisitor<T> {
+    type Value = ContextMap<T>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a list of contexts and their dies")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut map = HashMap::new();
+        while let Some((key, die)) = seq.next_element::<(Vec<Option<T>>, WeightedDie<T>)>()? {
+            map.insert(key, die);
+        }
+        Ok(ContextMap::Hash(map))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut entries: A) -> Result<Self::Value, A::Error> {
+        let mut map = HashMap::new();
+        while let Some((key, die)) = entries.next_entry::<Vec<Option<T>>, WeightedDie<T>>()? {
+            map.insert(key, die);
+        }
+        Ok(ContextMap::Hash(map))
+    }
+}
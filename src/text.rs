@@ -0,0 +1,264 @@
+//! Sentence generation from plain text.
+//!
+//! Text is split into sentences, and each sentence into
+//! tokens: either words and punctuation, or single
+//! graphemes. Tokens are fed to an InternedChain, with an
+//! empty boundary token padding the start of every sentence
+//! and marking its end. Tokens keep their
+//! case, and generated sentences are put back together with
+//! the usual spacing around punctuation.
+
+use cfg_if::cfg_if;
+
+#[cfg(feature = "serializer")]
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::rng::SplitMix64;
+use super::InternedChain;
+
+/// The token that pads the start of a sentence and marks
+/// its end. Tokenizers never produce empty tokens.
+const BOUNDARY: &str = "";
+
+/// How text is split into tokens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serializer", derive(Serialize, Deserialize))]
+pub enum Tokenizer {
+    /// Words and punctuation marks. Whitespace is dropped,
+    /// and put back when detokenizing.
+    Word,
+    /// Extended grapheme clusters, so "é" and emoji with
+    /// modifiers are single tokens. Runs of whitespace
+    /// become a single space.
+    Grapheme,
+}
+
+// No space goes before these when detokenizing words.
+const CLOSING: &[&str] = &[
+    ".", ",", "!", "?", ";", ":", ")", "]", "}", "%", "…", "'", "’", "”", "»",
+];
+// No space goes after these.
+const OPENING: &[&str] = &["(", "[", "{", "$", "“", "‘", "«", "¿", "¡"];
+
+impl Tokenizer {
+    /// Splits 'text' into sentences of tokens. Empty
+    /// sentences are left out.
+    pub fn sentences(&self, text: &str) -> Vec<Vec<String>> {
+        text.split_sentence_bounds()
+            .map(|sentence| self.tokens(sentence))
+            .filter(|tokens| !tokens.is_empty())
+            .collect()
+    }
+
+    fn tokens(&self, sentence: &str) -> Vec<String> {
+        match self {
+            Tokenizer::Word => sentence
+                .split_word_bounds()
+                .filter(|t| !t.trim().is_empty())
+                .map(String::from)
+                .collect(),
+            Tokenizer::Grapheme => sentence
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+                .graphemes(true)
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    /// Joins tokens back into text.
+    ///
+    /// Graphemes are concatenated. Words are separated by
+    /// spaces, except before closing punctuation and after
+    /// opening punctuation. Straight double quotes alternate
+    /// between opening and closing.
+    pub fn detokenize<S: AsRef<str>>(&self, tokens: &[S]) -> String {
+        let mut text = String::new();
+        if *self == Tokenizer::Grapheme {
+            for t in tokens {
+                text.push_str(t.as_ref());
+            }
+            return text;
+        }
+
+        let mut quote_open = false;
+        let mut glue_next = true;
+        for t in tokens {
+            let t = t.as_ref();
+            let (closing, opening) = match t {
+                "\"" => {
+                    quote_open = !quote_open;
+                    (!quote_open, quote_open)
+                }
+                _ => (CLOSING.contains(&t), OPENING.contains(&t)),
+            };
+            if !glue_next && !closing {
+                text.push(' ');
+            }
+            text.push_str(t);
+            glue_next = opening;
+        }
+        text
+    }
+}
+
+/// Generates sentences that read like the text it was
+/// trained on.
+#[derive(Clone)]
+#[cfg_attr(feature = "serializer", derive(Debug, Serialize, Deserialize))]
+pub struct TextGenerator {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+    chain: InternedChain<String>,
+}
+
+impl TextGenerator {
+    /// Creates a new TextGenerator that splits text with
+    /// 'tokenizer' and looks back 'order' tokens.
+    pub fn new(tokenizer: Tokenizer, order: usize) -> Self {
+        TextGenerator {
+            tokenizer,
+            max_tokens: 200,
+            chain: InternedChain::new(order, &[]),
+        }
+    }
+
+    /// Cuts generated sentences off after this many tokens.
+    /// The default is 200.
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Returns the tokenizer this generator splits text with.
+    pub fn tokenizer(&self) -> Tokenizer {
+        self.tokenizer
+    }
+
+    /// Returns the underlying chain. Its vocabulary holds
+    /// every token, plus an empty sentence boundary token.
+    pub fn chain(&self) -> &InternedChain<String> {
+        &self.chain
+    }
+
+    /// Feeds every sentence of 'text' into the model.
+    pub fn train(&mut self, text: &str) {
+        let order = self.chain.chain().order;
+        for sentence in self.tokenizer.sentences(text) {
+            let mut tokens = vec![BOUNDARY.to_string(); order];
+            tokens.extend(sentence);
+            tokens.push(BOUNDARY.to_string());
+            for i in order..tokens.len() {
+                self.chain.train(&tokens[i - order..i], &tokens[i], 1);
+            }
+        }
+    }
+
+    /// Builds a sentence, taking each token from 'roll'.
+    fn sentence<'a>(
+        &'a self,
+        mut roll: impl FnMut(&[String]) -> Option<&'a String>,
+    ) -> Option<String> {
+        let order = self.chain.chain().order;
+        let mut tokens = vec![BOUNDARY.to_string(); order];
+        while tokens.len() - order < self.max_tokens {
+            match roll(&tokens[tokens.len() - order..]) {
+                Some(t) if t != BOUNDARY => tokens.push(t.clone()),
+                _ => break,
+            }
+        }
+        match tokens.len() == order {
+            true => None,
+            false => Some(self.tokenizer.detokenize(&tokens[order..])),
+        }
+    }
+
+    /// Generates a sentence, or None if nothing has been
+    /// trained.
+    ///
+    /// Each token is rolled with a value derived from 'seed',
+    /// so the same seed always gives the same sentence.
+    pub fn generate_sentence_deterministic(&self, seed: u64) -> Option<String> {
+        let mut rng = SplitMix64::new(seed);
+        self.sentence(|view| self.chain.generate_deterministic(view, rng.next_u64()))
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "rand")] {
+            /// Generates a sentence, or None if nothing has been
+            /// trained.
+            pub fn generate_sentence(&self) -> Option<String> {
+                self.sentence(|view| self.chain.generate(view))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_tokens() {
+        let sentences = Tokenizer::Word.sentences("Hello, world! Don't  panic (yet).");
+        assert_eq!(
+            sentences,
+            vec![
+                vec!["Hello", ",", "world", "!"],
+                vec!["Don't", "panic", "(", "yet", ")", "."],
+            ]
+        );
+        assert_eq!(Tokenizer::Word.detokenize(&sentences[0]), "Hello, world!");
+        assert_eq!(
+            Tokenizer::Word.detokenize(&sentences[1]),
+            "Don't panic (yet)."
+        );
+        assert_eq!(
+            Tokenizer::Word.detokenize(&["He", "said", "\"", "hi", "\"", "."]),
+            "He said \"hi\"."
+        );
+    }
+
+    #[test]
+    fn grapheme_tokens() {
+        let sentences = Tokenizer::Grapheme.sentences("Café  ok.\nNo.");
+        assert_eq!(sentences.len(), 2);
+        // "e" and the combining accent stay together.
+        let cafe = "Cafe\u{301} ok.";
+        let tokens = Tokenizer::Grapheme.sentences(cafe).remove(0);
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(tokens[3], "e\u{301}");
+        assert_eq!(Tokenizer::Grapheme.detokenize(&tokens), cafe);
+    }
+
+    #[test]
+    fn generates_sentences() {
+        let mut g = TextGenerator::new(Tokenizer::Word, 2);
+        assert_eq!(g.generate_sentence_deterministic(1), None);
+
+        g.train("The cat sat on the mat. The dog sat on the log. A bird, however, flew!");
+        for seed in 0..20 {
+            let sentence = g.generate_sentence_deterministic(seed).unwrap();
+            assert!(
+                ["The cat", "The dog", "A bird"]
+                    .iter()
+                    .any(|start| sentence.starts_with(start)),
+                "{}",
+                sentence
+            );
+            assert!(sentence.ends_with('.') || sentence.ends_with('!'));
+        }
+        assert_eq!(
+            g.generate_sentence_deterministic(7),
+            g.generate_sentence_deterministic(7)
+        );
+        #[cfg(feature = "rand")]
+        assert!(g.generate_sentence().is_some());
+
+        let mut g = TextGenerator::new(Tokenizer::Grapheme, 3).max_tokens(5);
+        g.train("Abcdefghij.");
+        assert_eq!(g.generate_sentence_deterministic(0).unwrap(), "Abcde");
+    }
+}
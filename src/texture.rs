@@ -0,0 +1,179 @@
+//! Texture synthesis from overlapping NxN patterns, in the
+//! style of WaveFunctionCollapse.
+//!
+//! Every NxN window of the training image is a pattern. Each
+//! pixel is predicted from the rest of the window that has it
+//! in the bottom-right corner, and pixels are generated in
+//! row-major order, so the rest of that window is always
+//! already known. Near the top and left edges of the output,
+//! window cells outside the image are matched as unknown.
+//! Every NxN window of a generated image is a pattern from
+//! the training image.
+//!
+//! Load and save images with the pnm module, using pixel
+//! colors as elements.
+
+use super::grid::{GenerateOptions, Grid, GridError, GridModel, Neighborhood, Traversal};
+use super::Element;
+
+/// Learns the NxN patterns of images, and generates new
+/// images made of them.
+#[derive(Clone)]
+pub struct OverlappingModel<T: Element> {
+    n: usize,
+    model: GridModel<T>,
+}
+
+impl<T: Element> OverlappingModel<T> {
+    /// Creates a new OverlappingModel with NxN patterns.
+    ///
+    /// Panics if 'n' is less than 2.
+    pub fn new(n: usize) -> Self {
+        assert!(n >= 2, "patterns must be at least 2x2");
+        let n_i = n as isize;
+        let window = (1 - n_i..=0)
+            .flat_map(|dy| (1 - n_i..=0).map(move |dx| (dx, dy)))
+            .filter(|o| *o != (0, 0))
+            .collect();
+        OverlappingModel {
+            n,
+            model: GridModel::new(Neighborhood::Custom(window)),
+        }
+    }
+
+    /// Returns the size of the patterns.
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Returns the underlying model. Its view of a pixel is
+    /// the rest of the window above and to the left of it, in
+    /// row-major order.
+    pub fn model(&self) -> &GridModel<T> {
+        &self.model
+    }
+
+    /// Feeds every NxN window of 'image' into the model.
+    /// Windows with empty pixels are skipped.
+    pub fn train(&mut self, image: &Grid<T>) {
+        self.model.train(image);
+    }
+
+    /// Generates a 'width' by 'height' image.
+    ///
+    /// The traversal in 'options' is ignored: pixels are
+    /// always generated in row-major order.
+    pub fn generate(
+        &self,
+        width: usize,
+        height: usize,
+        options: &GenerateOptions,
+    ) -> Result<Grid<T>, GridError> {
+        self.fill(&Grid::new(width, height), options)
+    }
+
+    /// Fills in the empty pixels of 'image'. Pixels that
+    /// already have a value are kept as they are, and every
+    /// window they're part of must still be a pattern, so a
+    /// pixel that no pattern allows where it is gives an
+    /// error.
+    ///
+    /// The traversal in 'options' is ignored: pixels are
+    /// always generated in row-major order.
+    pub fn fill(&self, image: &Grid<T>, options: &GenerateOptions) -> Result<Grid<T>, GridError> {
+        self.model
+            .fill(image, &options.clone().traversal(Traversal::RowMajor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::Policy;
+    use super::super::pnm;
+    use super::*;
+
+    const B: [u8; 3] = [0, 0, 0];
+    const W: [u8; 3] = [255, 255, 255];
+
+    /// Every NxN window of 'image', in row-major order.
+    fn windows<T: Element>(image: &Grid<T>, n: usize) -> Vec<Vec<T>> {
+        let mut found = vec![];
+        for y in 0..=image.height() - n {
+            for x in 0..=image.width() - n {
+                found.push(
+                    (0..n * n)
+                        .map(|i| image.get(x + i % n, y + i / n).unwrap().clone())
+                        .collect(),
+                );
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn local_patterns_come_from_source() {
+        // Diagonal stripes, three pixels apart.
+        let rows: Vec<Vec<[u8; 3]>> = (0..9)
+            .map(|y| {
+                (0..9)
+                    .map(|x| match (x + y) % 3 {
+                        0 => B,
+                        _ => W,
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut bytes = vec![];
+        pnm::write_ppm(&mut bytes, &Grid::from_rows(&rows)).unwrap();
+        let source = pnm::read_ppm(&bytes[..]).unwrap();
+
+        let mut m = OverlappingModel::new(3);
+        m.train(&source);
+        assert_eq!(m.n(), 3);
+        assert_eq!(m.model().offsets().len(), 8);
+
+        let patterns = windows(&source, 3);
+        let options = GenerateOptions::new()
+            .policy(Policy::Backtrack(10_000))
+            .seed(11);
+        let image = m.generate(16, 12, &options).unwrap();
+        assert!(image.is_complete());
+        for w in windows(&image, 3) {
+            assert!(patterns.contains(&w));
+        }
+        assert_eq!(image, m.generate(16, 12, &options).unwrap());
+    }
+
+    #[test]
+    fn fills_around_fixed_pixels() {
+        let source = Grid::from_rows(&[
+            [1u8, 1, 2, 2, 1, 1],
+            [1, 1, 2, 2, 1, 1],
+            [2, 2, 1, 1, 2, 2],
+            [2, 2, 1, 1, 2, 2],
+        ]);
+        let mut m = OverlappingModel::new(2);
+        m.train(&source);
+        let mut seeded = Grid::new(4, 4);
+        seeded.set(1, 1, Some(2));
+        let options = GenerateOptions::new()
+            .policy(Policy::Backtrack(1000))
+            .seed(0);
+        let image = m.fill(&seeded, &options).unwrap();
+        assert_eq!(image.get(1, 1), Some(&2));
+        let patterns = windows(&source, 2);
+        for w in windows(&image, 2) {
+            assert!(patterns.contains(&w));
+        }
+
+        // No pattern has a 3 in it.
+        let mut seeded = Grid::new(4, 4);
+        seeded.set(2, 1, Some(3));
+        assert_eq!(m.fill(&seeded, &options), Err(GridError::Unsatisfiable));
+        let retry = options.policy(Policy::Retry(3));
+        assert_eq!(
+            m.fill(&seeded, &retry),
+            Err(GridError::Contradiction((2, 1)))
+        );
+    }
+}
@@ -0,0 +1,1072 @@
+//! Spaces of cells beyond square grids, like hex maps and
+//! voxel volumes.
+//!
+//! A Topology says how many cells there are, how they're laid
+//! out along each axis, and which cells neighbor each one. A
+//! TopologyModel learns from and fills in the cells of any
+//! topology, with one optional element of the view for each
+//! neighbor. GridModel is a TopologyModel over a square
+//! Lattice.
+
+use super::grid::{GenerateOptions, GridError, Neighborhood, Policy, Traversal};
+use super::rng::SplitMix64;
+use super::{Element, Indexing, MarkovChain, WeightedDie};
+
+/// A space of cells, and which cells neighbor each one.
+///
+/// Cells are numbered in row-major order over dims, with the
+/// first axis changing fastest.
+pub trait Topology {
+    /// Returns the size of each axis, fastest-changing first,
+    /// like [width, height, depth].
+    fn dims(&self) -> Vec<usize>;
+
+    /// Returns the neighbors of 'cell', in view order. A
+    /// neighbor is None if it's outside the space.
+    fn neighbors(&self, cell: usize) -> Vec<Option<usize>>;
+
+    /// Returns the number of cells.
+    fn len(&self) -> usize {
+        self.dims().iter().product()
+    }
+
+    /// Returns true if there are no cells.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns false if the space is a window onto a larger
+    /// one. Neighbors outside a window are unknown, rather
+    /// than the model's border.
+    fn bounded(&self) -> bool {
+        true
+    }
+
+    /// Returns the coordinates of 'cell' on each axis.
+    fn coords(&self, cell: usize) -> Vec<usize> {
+        let mut rest = cell;
+        self.dims()
+            .iter()
+            .map(|d| {
+                let c = rest % d;
+                rest /= d;
+                c
+            })
+            .collect()
+    }
+}
+
+/// Which voxels around a voxel make up its view.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VoxelNeighborhood {
+    /// The 6 voxels that share a face.
+    Faces,
+    /// All 26 surrounding voxels.
+    All,
+}
+
+/// A box of cells whose neighbors are at fixed offsets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lattice {
+    dims: Vec<usize>,
+    offsets: Vec<Vec<isize>>,
+}
+
+impl Lattice {
+    /// Creates a Lattice with any number of axes. Each offset
+    /// has one entry per axis.
+    ///
+    /// Panics if an offset has the wrong number of entries.
+    pub fn new(dims: &[usize], offsets: Vec<Vec<isize>>) -> Self {
+        assert!(
+            offsets.iter().all(|o| o.len() == dims.len()),
+            "every offset needs {} entries",
+            dims.len()
+        );
+        Lattice {
+            dims: dims.to_vec(),
+            offsets,
+        }
+    }
+
+    /// Creates a 2D grid. See Neighborhood.
+    pub fn grid(width: usize, height: usize, neighborhood: &Neighborhood) -> Self {
+        let offsets = neighborhood
+            .offsets()
+            .into_iter()
+            .map(|(dx, dy)| vec![dx, dy])
+            .collect();
+        Lattice::new(&[width, height], offsets)
+    }
+
+    /// Creates a hex map in axial coordinates, with q along
+    /// the first axis and r along the second. The map is a
+    /// rhombus, and each hex has 6 neighbors.
+    /// See Neighborhood::Hex.
+    pub fn hex(width: usize, height: usize) -> Self {
+        Lattice::grid(width, height, &Neighborhood::Hex)
+    }
+
+    /// Creates a 3D volume of voxels, with z along the third
+    /// axis.
+    pub fn voxels(
+        width: usize,
+        height: usize,
+        depth: usize,
+        neighborhood: VoxelNeighborhood,
+    ) -> Self {
+        let mut offsets = vec![];
+        for dz in -1..=1isize {
+            for dy in -1..=1isize {
+                for dx in -1..=1isize {
+                    let distance = dx.abs() + dy.abs() + dz.abs();
+                    let keep = match neighborhood {
+                        VoxelNeighborhood::Faces => distance == 1,
+                        VoxelNeighborhood::All => distance > 0,
+                    };
+                    if keep {
+                        offsets.push(vec![dx, dy, dz]);
+                    }
+                }
+            }
+        }
+        Lattice::new(&[width, height, depth], offsets)
+    }
+
+    /// Returns the offset of each neighbor, in view order.
+    pub fn offsets(&self) -> &[Vec<isize>] {
+        &self.offsets
+    }
+
+    /// Returns the cell at 'coords', or None if that's
+    /// outside the lattice.
+    pub fn index(&self, coords: &[usize]) -> Option<usize> {
+        if coords.len() != self.dims.len() {
+            return None;
+        }
+        let mut index = 0;
+        for (c, d) in coords.iter().zip(&self.dims).rev() {
+            if c >= d {
+                return None;
+            }
+            index = index * d + c;
+        }
+        Some(index)
+    }
+}
+
+impl Topology for Lattice {
+    fn dims(&self) -> Vec<usize> {
+        self.dims.clone()
+    }
+
+    fn neighbors(&self, cell: usize) -> Vec<Option<usize>> {
+        let coords = self.coords(cell);
+        self.offsets
+            .iter()
+            .map(|offset| {
+                let moved: Option<Vec<usize>> = coords
+                    .iter()
+                    .zip(offset)
+                    .map(|(c, o)| c.checked_add_signed(*o))
+                    .collect();
+                self.index(&moved?)
+            })
+            .collect()
+    }
+}
+
+/// A space whose cells see their neighbors in a view of
+/// their own making.
+pub(crate) trait Views<T>: Topology {
+    /// Returns the view of 'cell', None where it's unknown.
+    fn view(&self, cells: &[Option<T>], cell: usize) -> Vec<Option<T>>;
+}
+
+/// A Topology seen the way a TopologyModel sees it, with its
+/// border around it.
+struct Bordered<'a, S, T: Element> {
+    space: &'a S,
+    model: &'a TopologyModel<T>,
+}
+
+impl<'a, S: Topology, T: Element> Topology for Bordered<'a, S, T> {
+    fn dims(&self) -> Vec<usize> {
+        self.space.dims()
+    }
+
+    fn neighbors(&self, cell: usize) -> Vec<Option<usize>> {
+        self.space.neighbors(cell)
+    }
+
+    fn bounded(&self) -> bool {
+        self.space.bounded()
+    }
+}
+
+impl<'a, S: Topology, T: Element> Views<T> for Bordered<'a, S, T> {
+    fn view(&self, cells: &[Option<T>], cell: usize) -> Vec<Option<T>> {
+        self.model.view(self.space, cells, cell)
+    }
+}
+
+/// Cells being filled in. Every cell is pending until it has
+/// been visited. Fixed cells keep their value throughout.
+struct Fill<'a, T> {
+    cells: Vec<Option<T>>,
+    fixed: Vec<bool>,
+    pending: Vec<bool>,
+    seen_by: &'a [Vec<usize>],
+    allows: &'a dyn Fn(&[Option<T>]) -> bool,
+}
+
+impl<'a, T: Clone> Fill<'a, T> {
+    fn new(
+        cells: &[Option<T>],
+        seen_by: &'a [Vec<usize>],
+        allows: &'a dyn Fn(&[Option<T>]) -> bool,
+    ) -> Self {
+        Fill {
+            cells: cells.to_vec(),
+            fixed: cells.iter().map(Option::is_some).collect(),
+            pending: vec![true; cells.len()],
+            seen_by,
+            allows,
+        }
+    }
+
+    /// Returns false if the cells so far can't be completed
+    /// in a way the constraints allow.
+    fn allowed(&self) -> bool {
+        (self.allows)(&self.cells)
+    }
+
+    fn visit(&mut self, cell: usize, value: T) {
+        self.cells[cell] = Some(value);
+        self.pending[cell] = false;
+    }
+
+    fn unvisit(&mut self, cell: usize) {
+        if !self.fixed[cell] {
+            self.cells[cell] = None;
+        }
+        self.pending[cell] = true;
+    }
+
+    /// Unwraps the cells, once every one is filled.
+    fn finish(self) -> Vec<T> {
+        self.cells.into_iter().flatten().collect()
+    }
+}
+
+/// How much less likely refine makes a value for each
+/// context it leaves untrained.
+const CONFLICT_PENALTY: u64 = 8;
+
+/// The result of refining cells. See TopologyModel::refine.
+///
+/// 'C' holds the cells, and 'P' is the coordinates of a cell:
+/// a Vec for any topology, or (x, y) for a GridModel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Refinement<C, P = Vec<usize>> {
+    cells: C,
+    conflicts: Vec<P>,
+    sweeps: usize,
+}
+
+impl<C, P> Refinement<C, P> {
+    /// Returns the refined cells.
+    pub fn cells(&self) -> &C {
+        &self.cells
+    }
+
+    /// Returns the refined cells.
+    pub fn into_cells(self) -> C {
+        self.cells
+    }
+
+    /// Returns the coordinates of every cell that's still in
+    /// conflict. See TopologyModel::conflicts.
+    pub fn conflicts(&self) -> &[P] {
+        &self.conflicts
+    }
+
+    /// Returns the number of sweeps it took.
+    pub fn sweeps(&self) -> usize {
+        self.sweeps
+    }
+
+    /// Returns true if no cells are in conflict.
+    pub fn is_consistent(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Converts the cells with 'f', and the coordinates of
+    /// each conflict with 'at'.
+    pub(crate) fn map<D, Q>(self, f: impl FnOnce(C) -> D, at: impl Fn(P) -> Q) -> Refinement<D, Q> {
+        Refinement {
+            cells: f(self.cells),
+            conflicts: self.conflicts.into_iter().map(at).collect(),
+            sweeps: self.sweeps,
+        }
+    }
+}
+
+/// A cell that backtracking has filled in, and the values it
+/// has left to try there.
+struct Choice<T> {
+    cell: usize,
+    resume: usize,
+    left: Vec<T>,
+}
+
+/// Learns and generates the cells of a Topology.
+#[derive(Clone)]
+pub struct TopologyModel<T: Element> {
+    border: Option<T>,
+    chain: MarkovChain<T>,
+}
+
+impl<T: Element> TopologyModel<T> {
+    /// Creates a new TopologyModel for topologies where each
+    /// cell has 'degree' neighbors.
+    ///
+    /// The chain stores only full neighborhoods, and matches
+    /// unknown neighbors when generating. See
+    /// Indexing::Wildcard.
+    pub fn new(degree: usize) -> Self {
+        let optional: Vec<usize> = (0..degree).collect();
+        let mut chain = MarkovChain::new(degree, &optional);
+        chain.set_indexing(Indexing::Wildcard);
+        TopologyModel {
+            border: None,
+            chain,
+        }
+    }
+
+    /// Treats every neighbor outside the topology as
+    /// 'border', both when training and when generating.
+    ///
+    /// Without a border, cells with neighbors outside aren't
+    /// trained on, and outside neighbors are unknown when
+    /// generating.
+    pub fn border(mut self, border: T) -> Self {
+        self.border = Some(border);
+        self
+    }
+
+    /// Returns the border, if there is one.
+    pub(crate) fn border_value(&self) -> Option<&T> {
+        self.border.as_ref()
+    }
+
+    /// Returns the number of neighbors each cell has.
+    pub fn degree(&self) -> usize {
+        self.chain.order
+    }
+
+    /// Returns the underlying chain.
+    pub fn chain(&self) -> &MarkovChain<T> {
+        &self.chain
+    }
+
+    /// Returns the view of 'cell': its neighbors, None where
+    /// they're empty or unknown.
+    ///
+    /// Panics if the topology gives 'cell' the wrong number
+    /// of neighbors.
+    pub fn view<S: Topology>(&self, space: &S, cells: &[Option<T>], cell: usize) -> Vec<Option<T>> {
+        let neighbors = space.neighbors(cell);
+        assert_eq!(
+            neighbors.len(),
+            self.degree(),
+            "cell {} has the wrong number of neighbors",
+            cell
+        );
+        let bounded = space.bounded();
+        neighbors
+            .into_iter()
+            .map(|n| match n {
+                Some(n) => cells[n].clone(),
+                None if bounded => self.border.clone(),
+                None => None,
+            })
+            .collect()
+    }
+
+    /// Feeds every filled cell whose neighbors are all known
+    /// into the model.
+    ///
+    /// Panics if 'cells' doesn't have one entry per cell of
+    /// 'space'.
+    pub fn train<S: Topology>(&mut self, space: &S, cells: &[Option<T>]) {
+        check_len(space, cells);
+        let views = (0..cells.len())
+            .map(|c| self.view(space, cells, c))
+            .collect();
+        self.train_views(cells, views);
+    }
+
+    /// Feeds every filled cell whose view is complete into
+    /// the model.
+    pub(crate) fn train_views(&mut self, cells: &[Option<T>], views: Vec<Vec<Option<T>>>) {
+        for (result, view) in cells.iter().zip(views) {
+            let result = match result {
+                Some(v) => v.clone(),
+                None => continue,
+            };
+            let view: Option<Vec<T>> = view.into_iter().collect();
+            if let Some(view) = view {
+                self.chain.train(&view, result, 1);
+            }
+        }
+    }
+
+    /// Generates every cell of 'space'.
+    pub fn generate<S: Topology>(
+        &self,
+        space: &S,
+        options: &GenerateOptions,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        self.fill(space, &vec![None; space.len()], options)
+    }
+
+    /// Fills in the empty cells of 'space'.
+    ///
+    /// Cells that already have a value are kept as they are.
+    /// They're visited in turn like the rest, and their value
+    /// must be one the model could have generated there.
+    ///
+    /// Panics if 'cells' doesn't have one entry per cell of
+    /// 'space'.
+    pub fn fill<S: Topology>(
+        &self,
+        space: &S,
+        cells: &[Option<T>],
+        options: &GenerateOptions,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        check_len(space, cells);
+        self.fill_allowed(space, cells, options, &|_| true)
+    }
+
+    /// Fills in the empty cells of 'space', like fill. Whenever
+    /// a cell is filled, 'allows' must accept the cells so far.
+    pub(crate) fn fill_allowed<S: Topology>(
+        &self,
+        space: &S,
+        cells: &[Option<T>],
+        options: &GenerateOptions,
+        allows: &dyn Fn(&[Option<T>]) -> bool,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        let mut rng = SplitMix64::from_seed(options.seed);
+        let order = visit_order(space, options.traversal, &mut rng);
+        self.fill_in_order(
+            space,
+            cells,
+            options,
+            &order,
+            &mut |_| rng.next_u64(),
+            allows,
+        )
+    }
+
+    /// Fills in the empty cells of 'space', visiting them in
+    /// 'order'. Each roll at a cell takes its random number
+    /// from 'roll', called with the cell. Whenever a cell is
+    /// filled, 'allows' must accept the cells so far.
+    pub(crate) fn fill_in_order<S: Topology>(
+        &self,
+        space: &S,
+        cells: &[Option<T>],
+        options: &GenerateOptions,
+        order: &[usize],
+        roll: &mut dyn FnMut(usize) -> u64,
+        allows: &dyn Fn(&[Option<T>]) -> bool,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        let views = Bordered { space, model: self };
+        self.fill_views(&views, cells, options, order, roll, allows)
+    }
+
+    /// Fills in the empty cells of 'space' like fill_in_order,
+    /// with the views 'space' makes.
+    pub(crate) fn fill_views<V: Views<T>>(
+        &self,
+        space: &V,
+        cells: &[Option<T>],
+        options: &GenerateOptions,
+        order: &[usize],
+        roll: &mut dyn FnMut(usize) -> u64,
+        allows: &dyn Fn(&[Option<T>]) -> bool,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        if !allows(cells) {
+            return Err(GridError::Unsatisfiable);
+        }
+        let seen_by = seen_by(space);
+        match options.policy {
+            Policy::Retry(attempts) => {
+                let mut failed = GridError::Unsatisfiable;
+                for _ in 0..attempts.max(1) {
+                    let mut attempt = Fill::new(cells, &seen_by, allows);
+                    match self.fill_forward(space, &mut attempt, options.traversal, order, roll) {
+                        Ok(()) => return Ok(attempt.finish()),
+                        Err(e) => failed = e,
+                    }
+                }
+                Err(failed)
+            }
+            Policy::Backtrack(max_steps) => self.fill_backtracking(
+                space,
+                Fill::new(cells, &seen_by, allows),
+                options.traversal,
+                order,
+                roll,
+                max_steps,
+            ),
+        }
+    }
+
+    /// Returns the coordinates of every cell in conflict: the
+    /// empty ones, and those whose value never followed their
+    /// neighbors in training.
+    ///
+    /// Panics if 'cells' doesn't have one entry per cell of
+    /// 'space'.
+    pub fn conflicts<S: Topology>(&self, space: &S, cells: &[Option<T>]) -> Vec<Vec<usize>> {
+        check_len(space, cells);
+        self.conflicted(space, cells)
+            .into_iter()
+            .map(|c| space.coords(c))
+            .collect()
+    }
+
+    fn conflicted<S: Topology>(&self, space: &S, cells: &[Option<T>]) -> Vec<usize> {
+        (0..cells.len())
+            .filter(|c| !self.fits(space, cells, *c))
+            .collect()
+    }
+
+    fn fits<S: Topology>(&self, space: &S, cells: &[Option<T>], cell: usize) -> bool {
+        match &cells[cell] {
+            Some(v) => self.chain.probability(&self.view(space, cells, cell), v) > 0.0,
+            None => false,
+        }
+    }
+
+    /// Rolls a value for 'cell' from its known neighbors, or
+    /// from no neighbors at all if they match nothing.
+    fn roll_relaxed<S: Topology>(
+        &self,
+        space: &S,
+        cells: &[Option<T>],
+        cell: usize,
+        rng: &mut SplitMix64,
+    ) -> Option<T> {
+        let view = self.view(space, cells, cell);
+        self.chain
+            .generate_deterministic_from_partial(&view, rng.next_u64())
+            .or_else(|| {
+                self.chain
+                    .generate_deterministic_from_partial(&vec![None; view.len()], rng.next_u64())
+            })
+    }
+
+    /// Resamples 'cell' given all of its neighbors.
+    ///
+    /// Every value the model knows is weighted by how often
+    /// it followed the cell's view, or how often it was seen
+    /// at all if it never did. Then, for each context the
+    /// value would leave untrained, among the cell's own and
+    /// those of the cells that see it, the weight is cut by
+    /// a factor of CONFLICT_PENALTY. This is Gibbs sampling
+    /// at a low temperature.
+    fn resample<S: Topology>(
+        &self,
+        space: &S,
+        cells: &mut [Option<T>],
+        seen_by: &[Vec<usize>],
+        cell: usize,
+        rng: &mut SplitMix64,
+    ) -> Option<T> {
+        let view = self.view(space, cells, cell);
+        let own = self.chain.distribution(&view);
+        let old = cells[cell].take();
+        let mut scored: Vec<(T, u64, usize)> = vec![];
+        for (v, w) in self.chain.distribution(&vec![None; view.len()]) {
+            let own_weight = own.iter().find(|s| s.0 == v).map(|s| s.1);
+            cells[cell] = Some(v.clone());
+            let misses = own_weight.is_none() as usize
+                + seen_by[cell]
+                    .iter()
+                    .filter(|n| !self.fits(space, cells, **n))
+                    .count();
+            scored.push((v, own_weight.unwrap_or(w), misses));
+        }
+        cells[cell] = old;
+
+        let fewest = scored.iter().map(|s| s.2).min()?;
+        let scored: Vec<(T, f64)> = scored
+            .into_iter()
+            .map(|(v, w, misses)| {
+                let penalty = CONFLICT_PENALTY.saturating_pow((misses - fewest) as u32);
+                (v, w as f64 / penalty.max(1) as f64)
+            })
+            .collect();
+        // Scaled so the weights add up to about 2^52, which
+        // keeps penalized weights above zero without overflow.
+        let total: f64 = scored.iter().map(|s| s.1).sum();
+        scored
+            .into_iter()
+            .map(|(v, w)| (v, (w / total * (1u64 << 52) as f64) as u64))
+            .filter(|s| s.1 > 0)
+            .collect::<WeightedDie<T>>()
+            .roll(Some(rng.next_u64()))
+    }
+
+    /// Fills in the empty cells of 'space' roughly, then
+    /// improves the fill with Gibbs sampling.
+    ///
+    /// The rough fill never gets stuck: cells whose known
+    /// neighbors match nothing are rolled as if they had no
+    /// neighbors. Then each sweep resamples the cells in
+    /// conflict and their neighbors, given all of their
+    /// neighbors, until no cell is in conflict or
+    /// 'max_sweeps' sweeps are done. Resampling strongly
+    /// favors the values that leave the fewest conflicts
+    /// around a cell, so it settles quickly, but it can still
+    /// climb out of a dead end. See conflicts.
+    ///
+    /// Cells that already have a value are never changed.
+    /// Cells are visited in the order of the traversal in
+    /// 'options', and its policy is ignored. Cells are only
+    /// left empty if nothing has been trained.
+    ///
+    /// Panics if 'cells' doesn't have one entry per cell of
+    /// 'space'.
+    pub fn refine<S: Topology>(
+        &self,
+        space: &S,
+        cells: &[Option<T>],
+        max_sweeps: usize,
+        options: &GenerateOptions,
+    ) -> Refinement<Vec<Option<T>>> {
+        check_len(space, cells);
+        let mut rng = SplitMix64::from_seed(options.seed);
+        let order = visit_order(space, options.traversal, &mut rng);
+        let fixed: Vec<bool> = cells.iter().map(Option::is_some).collect();
+        let mut cells = cells.to_vec();
+        for &c in &order {
+            if cells[c].is_none() {
+                cells[c] = self.roll_relaxed(space, &cells, c, &mut rng);
+            }
+        }
+
+        let neighbors: Vec<Vec<usize>> = (0..cells.len())
+            .map(|c| space.neighbors(c).into_iter().flatten().collect())
+            .collect();
+        let seen_by = seen_by(space);
+
+        let mut sweeps = 0;
+        let mut conflicts = self.conflicted(space, &cells);
+        while !conflicts.is_empty() && sweeps < max_sweeps {
+            sweeps += 1;
+            let mut hot = vec![false; cells.len()];
+            for c in conflicts {
+                hot[c] = true;
+                for n in &neighbors[c] {
+                    hot[*n] = true;
+                }
+            }
+            for &c in &order {
+                if hot[c] && !fixed[c] {
+                    cells[c] = self.resample(space, &mut cells, &seen_by, c, &mut rng);
+                }
+            }
+            conflicts = self.conflicted(space, &cells);
+        }
+        Refinement {
+            conflicts: conflicts.into_iter().map(|c| space.coords(c)).collect(),
+            cells,
+            sweeps,
+        }
+    }
+
+    /// Finds the next cell to visit, looking at 'order' from
+    /// position 'from'. Returns its position in 'order', and
+    /// the cell.
+    fn next_cell<V: Views<T>>(
+        &self,
+        space: &V,
+        fill: &Fill<T>,
+        traversal: Traversal,
+        order: &[usize],
+        from: usize,
+    ) -> Option<(usize, usize)> {
+        if traversal == Traversal::MostConstrained {
+            let filled = |cell: usize| {
+                space
+                    .neighbors(cell)
+                    .into_iter()
+                    .flatten()
+                    .filter(|n| fill.cells[*n].is_some())
+                    .count()
+            };
+            return (0..fill.cells.len())
+                .filter(|c| fill.pending[*c])
+                .max_by_key(|c| (filled(*c), std::cmp::Reverse(*c)))
+                .map(|c| (0, c));
+        }
+        order[from..]
+            .iter()
+            .position(|c| fill.pending[*c])
+            .map(|i| (from + i, order[from + i]))
+    }
+
+    /// Returns the value of a fixed cell, if its neighbors
+    /// allow it.
+    fn check_fixed<V: Views<T>>(&self, space: &V, fill: &Fill<T>, cell: usize) -> Option<T> {
+        let value = fill.cells[cell].clone()?;
+        let view = space.view(&fill.cells, cell);
+        match self.chain.probability(&view, &value) > 0.0 {
+            true => Some(value),
+            false => None,
+        }
+    }
+
+    /// Returns a filled cell that sees 'cell' and no longer
+    /// fits its view now that 'cell' is filled, if any.
+    fn misfit_seer<V: Views<T>>(&self, space: &V, fill: &Fill<T>, cell: usize) -> Option<usize> {
+        fill.seen_by[cell]
+            .iter()
+            .copied()
+            .find(|n| match &fill.cells[*n] {
+                Some(v) => self.chain.probability(&space.view(&fill.cells, *n), v) == 0.0,
+                None => false,
+            })
+    }
+
+    /// Visits every pending cell in one pass.
+    fn fill_forward<V: Views<T>>(
+        &self,
+        space: &V,
+        fill: &mut Fill<T>,
+        traversal: Traversal,
+        order: &[usize],
+        roll: &mut dyn FnMut(usize) -> u64,
+    ) -> Result<(), GridError<Vec<usize>>> {
+        let mut from = 0;
+        while let Some((i, cell)) = self.next_cell(space, fill, traversal, order, from) {
+            let value = match fill.fixed[cell] {
+                true => self.check_fixed(space, fill, cell),
+                false => self.chain.generate_deterministic_from_partial(
+                    &space.view(&fill.cells, cell),
+                    roll(cell),
+                ),
+            };
+            let value = value.ok_or_else(|| GridError::Contradiction(space.coords(cell)))?;
+            fill.visit(cell, value);
+            if let Some(n) = self.misfit_seer(space, fill, cell) {
+                return Err(GridError::Contradiction(space.coords(n)));
+            }
+            if !fill.allowed() {
+                return Err(GridError::Constraint(space.coords(cell)));
+            }
+            from = i + 1;
+        }
+        Ok(())
+    }
+
+    /// Returns every value that fits 'cell', in a weighted
+    /// random order.
+    fn candidates<V: Views<T>>(
+        &self,
+        space: &V,
+        fill: &Fill<T>,
+        cell: usize,
+        roll: &mut dyn FnMut(usize) -> u64,
+    ) -> Vec<T> {
+        if fill.fixed[cell] {
+            return self.check_fixed(space, fill, cell).into_iter().collect();
+        }
+        let die: WeightedDie<T> = self
+            .chain
+            .distribution(&space.view(&fill.cells, cell))
+            .into_iter()
+            .collect();
+        let rolls: Vec<u64> = (0..die.len()).map(|_| roll(cell)).collect();
+        die.sample_without_replacement(die.len(), Some(&rolls))
+    }
+
+    /// Visits every pending cell, going back to earlier cells
+    /// and trying their other values on a dead end.
+    fn fill_backtracking<V: Views<T>>(
+        &self,
+        space: &V,
+        mut fill: Fill<T>,
+        traversal: Traversal,
+        order: &[usize],
+        roll: &mut dyn FnMut(usize) -> u64,
+        max_steps: usize,
+    ) -> Result<Vec<T>, GridError<Vec<usize>>> {
+        let mut stack: Vec<Choice<T>> = vec![];
+        let mut from = 0;
+        let mut steps = 0;
+        loop {
+            let (resume, cell) = match self.next_cell(space, &fill, traversal, order, from) {
+                Some(next) => next,
+                None => return Ok(fill.finish()),
+            };
+            let mut left = self.candidates(space, &fill, cell, roll);
+            // Tried from the back.
+            left.reverse();
+            stack.push(Choice {
+                cell,
+                resume: resume + 1,
+                left,
+            });
+
+            loop {
+                let top = stack.last_mut().ok_or(GridError::Unsatisfiable)?;
+                match top.left.pop() {
+                    Some(v) => {
+                        if steps == max_steps {
+                            return Err(GridError::OutOfSteps);
+                        }
+                        steps += 1;
+                        fill.visit(top.cell, v);
+                        if self.misfit_seer(space, &fill, top.cell).is_some() || !fill.allowed() {
+                            fill.unvisit(top.cell);
+                            continue;
+                        }
+                        from = top.resume;
+                        break;
+                    }
+                    None => {
+                        fill.unvisit(top.cell);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Panics unless there's one entry in 'cells' per cell.
+fn check_len<S: Topology, T>(space: &S, cells: &[Option<T>]) {
+    assert_eq!(cells.len(), space.len(), "every cell needs a value or None");
+}
+
+/// Lists, for each cell, the cells that have it as a
+/// neighbor.
+fn seen_by<S: Topology>(space: &S) -> Vec<Vec<usize>> {
+    let mut seen_by = vec![vec![]; space.len()];
+    for c in 0..space.len() {
+        for n in space.neighbors(c).into_iter().flatten() {
+            seen_by[n].push(c);
+        }
+    }
+    seen_by
+}
+
+/// Lists every cell in the order 'traversal' visits them.
+/// MostConstrained picks as it goes, and ignores this.
+pub(crate) fn visit_order<S: Topology>(
+    space: &S,
+    traversal: Traversal,
+    rng: &mut SplitMix64,
+) -> Vec<usize> {
+    let dims = space.dims();
+    let len = space.len();
+    let width = dims.first().copied().unwrap_or(1).max(1);
+    match traversal {
+        Traversal::RowMajor | Traversal::MostConstrained => (0..len).collect(),
+        Traversal::ColumnMajor if dims.len() >= 2 => {
+            // Walk the first two axes transposed.
+            let height = dims[1].max(1);
+            (0..len)
+                .map(|i| {
+                    let (plane, rest) = (i / (width * height), i % (width * height));
+                    let (x, y) = (rest / height, rest % height);
+                    plane * width * height + y * width + x
+                })
+                .collect()
+        }
+        Traversal::ColumnMajor => (0..len).collect(),
+        Traversal::Serpentine => (0..len)
+            .map(|i| match (i / width) % 2 {
+                0 => i,
+                _ => i - i % width + width - 1 - i % width,
+            })
+            .collect(),
+        Traversal::Shuffled => {
+            let mut order: Vec<usize> = (0..len).collect();
+            for i in (1..order.len()).rev() {
+                order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+            }
+            order
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lattice_neighbors() {
+        let hex = Lattice::hex(3, 3);
+        assert_eq!(hex.len(), 9);
+        // The center hex has all 6 neighbors.
+        let center = hex.index(&[1, 1]).unwrap();
+        assert_eq!(
+            hex.neighbors(center),
+            vec![Some(5), Some(2), Some(1), Some(3), Some(6), Some(7)]
+        );
+        // A corner hex has 2 or 3 of them.
+        assert_eq!(hex.neighbors(0).iter().flatten().count(), 2);
+        assert_eq!(hex.neighbors(2).iter().flatten().count(), 3);
+
+        let faces = Lattice::voxels(3, 3, 3, VoxelNeighborhood::Faces);
+        let all = Lattice::voxels(3, 3, 3, VoxelNeighborhood::All);
+        assert_eq!(faces.offsets().len(), 6);
+        assert_eq!(all.offsets().len(), 26);
+        let center = all.index(&[1, 1, 1]).unwrap();
+        assert_eq!(center, 13);
+        assert_eq!(all.coords(center), vec![1, 1, 1]);
+        assert_eq!(all.neighbors(center).iter().flatten().count(), 26);
+        assert_eq!(all.neighbors(0).iter().flatten().count(), 7);
+        assert_eq!(
+            faces.neighbors(center),
+            vec![Some(4), Some(10), Some(12), Some(14), Some(16), Some(22)]
+        );
+        assert_eq!(all.index(&[3, 0, 0]), None);
+    }
+
+    #[test]
+    fn visit_orders() {
+        let space = Lattice::grid(3, 2, &Neighborhood::VonNeumann);
+        let mut rng = SplitMix64::new(0);
+        let mut order = |t| visit_order(&space, t, &mut rng);
+        assert_eq!(order(Traversal::RowMajor), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(order(Traversal::ColumnMajor), vec![0, 3, 1, 4, 2, 5]);
+        assert_eq!(order(Traversal::Serpentine), vec![0, 1, 2, 5, 4, 3]);
+        let mut shuffled = order(Traversal::Shuffled);
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn layered_voxels() {
+        // Each layer is one material: stone, then dirt, then
+        // grass, with air above.
+        let layers = ['s', 'd', 'g', 'a'];
+        let space = Lattice::voxels(3, 3, 4, VoxelNeighborhood::Faces);
+        let cells: Vec<Option<char>> = (0..space.len())
+            .map(|i| Some(layers[space.coords(i)[2]]))
+            .collect();
+        let mut m = TopologyModel::new(6).border('x');
+        m.train(&space, &cells);
+        assert_eq!(m.degree(), 6);
+
+        let bigger = Lattice::voxels(5, 4, 4, VoxelNeighborhood::Faces);
+        let options = GenerateOptions::new()
+            .policy(Policy::Backtrack(10_000))
+            .traversal(Traversal::Shuffled)
+            .seed(5);
+        let voxels = m.generate(&bigger, &options).unwrap();
+        for (i, v) in voxels.iter().enumerate() {
+            assert_eq!(*v, layers[bigger.coords(i)[2]]);
+        }
+    }
+
+    #[test]
+    fn hex_rings() {
+        // Hexes alternate between two kinds in stripes along
+        // q, so no two neighbors match along the q axis.
+        let space = Lattice::hex(4, 4);
+        let cells: Vec<Option<u8>> = (0..space.len())
+            .map(|i| Some((space.coords(i)[0] % 2) as u8))
+            .collect();
+        let mut m = TopologyModel::new(6);
+        m.train(&space, &cells);
+
+        let mut seeded = vec![None; space.len()];
+        seeded[5] = Some(1);
+        let options = GenerateOptions::new()
+            .policy(Policy::Backtrack(1000))
+            .seed(1);
+        let hexes = m.fill(&space, &seeded, &options).unwrap();
+        assert_eq!(hexes[5], 1);
+        for cell in 0..space.len() {
+            let east = space.neighbors(cell)[0];
+            if let Some(east) = east {
+                assert_ne!(hexes[cell], hexes[east]);
+            }
+        }
+    }
+
+    #[test]
+    fn refines_away_conflicts() {
+        // Neighbors always differ, and the border counts.
+        let space = Lattice::grid(5, 5, &Neighborhood::VonNeumann);
+        let mut m = TopologyModel::new(4).border('#');
+        for parity in 0..2 {
+            let cells: Vec<Option<char>> = (0..25)
+                .map(|i| Some(if i % 2 == parity { 'x' } else { 'o' }))
+                .collect();
+            m.train(&space, &cells);
+        }
+
+        let bigger = Lattice::grid(7, 7, &Neighborhood::VonNeumann);
+        let options = GenerateOptions::new()
+            .traversal(Traversal::Shuffled)
+            .seed(2);
+        let rough = m.refine(&bigger, &[None; 49], 0, &options);
+        assert_eq!(rough.sweeps(), 0);
+        assert!(!rough.is_consistent());
+        assert_eq!(rough.conflicts(), &m.conflicts(&bigger, rough.cells())[..]);
+
+        let refined = m.refine(&bigger, &[None; 49], 1000, &options);
+        assert!(refined.is_consistent(), "{:?}", refined.conflicts());
+        assert!(refined.sweeps() > 0);
+        let cells = refined.into_cells();
+        assert!(m.conflicts(&bigger, &cells).is_empty());
+
+        // Two fixed neighbors that match can't be fixed.
+        let mut seeded = vec![None; 49];
+        seeded[24] = Some('x');
+        seeded[25] = Some('x');
+        let stuck = m.refine(&bigger, &seeded, 20, &options);
+        assert_eq!(stuck.sweeps(), 20);
+        assert!(stuck.conflicts().contains(&vec![3, 3]));
+        assert!(stuck.conflicts().contains(&vec![4, 3]));
+        assert_eq!(stuck.cells()[24], Some('x'));
+
+        let empty: TopologyModel<char> = TopologyModel::new(4);
+        let nothing = empty.refine(&space, &[None; 25], 5, &options);
+        assert_eq!(nothing.conflicts().len(), 25);
+    }
+
+    #[test]
+    fn refines_heavy_weights() {
+        // The same checkerboards, with weights far past 2^44.
+        let space = Lattice::grid(5, 5, &Neighborhood::VonNeumann);
+        let mut m = TopologyModel::new(4).border('#');
+        for parity in 0..2 {
+            let cells: Vec<Option<char>> = (0..25)
+                .map(|i| Some(if i % 2 == parity { 'x' } else { 'o' }))
+                .collect();
+            for (c, cell) in cells.iter().enumerate() {
+                let view: Vec<char> = m.view(&space, &cells, c).into_iter().flatten().collect();
+                m.chain.train_weight(&view, cell.unwrap(), 1 << 50);
+            }
+        }
+        let bigger = Lattice::grid(7, 7, &Neighborhood::VonNeumann);
+        let options = GenerateOptions::new()
+            .traversal(Traversal::Shuffled)
+            .seed(2);
+        let rough = m.refine(&bigger, &[None; 49], 0, &options);
+        assert!(!rough.is_consistent());
+        let refined = m.refine(&bigger, &[None; 49], 1000, &options);
+        assert!(refined.is_consistent(), "{:?}", refined.conflicts());
+    }
+}
@@ -0,0 +1,293 @@
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+
+use super::die::WeightedDie;
+use super::frozen::FnvHasher;
+use super::storage::Entry;
+use super::Element;
+
+/// Marks a node without a die.
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone)]
+struct Node<T: Element> {
+    element: Option<T>,
+    // index into dies, or NONE.
+    die: u32,
+    // sorted by the hash of their element.
+    children: Box<[u32]>,
+}
+
+impl<T: Element> Node<T> {
+    fn new(element: Option<T>) -> Self {
+        Node {
+            element,
+            die: NONE,
+            children: Box::new([]),
+        }
+    }
+}
+
+fn hash_element<T: Element>(element: &Option<T>) -> u64 {
+    let mut hasher = FnvHasher::default();
+    element.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Turns a length into an index, which must fit in a u32.
+fn index(len: usize) -> u32 {
+    match u32::try_from(len) {
+        Ok(i) if i != NONE => i,
+        _ => panic!("a trie holds at most 2^32 - 1 nodes"),
+    }
+}
+
+/// Stores dies keyed by context, sharing storage between
+/// contexts that end the same way.
+///
+/// Contexts are inserted newest element first: the root's
+/// children are the last elements of contexts, their children
+/// the second-to-last, and so on. Every context below a node
+/// is a longer history that ends with that node's context.
+///
+/// Each node keeps its element, a u32 die index and a list of
+/// u32 child indices, sorted by the hash of their elements so
+/// a child can be found with a binary search. Keys are worked
+/// out while walking down, so nodes don't point back up.
+///
+/// Panics past 2^32 - 1 nodes.
+#[derive(Clone)]
+pub(crate) struct ContextTrie<T: Element> {
+    nodes: Vec<Node<T>>,
+    dies: Vec<WeightedDie<T>>,
+}
+
+impl<T: Element> ContextTrie<T> {
+    pub(crate) fn new() -> Self {
+        ContextTrie {
+            nodes: vec![Node::new(None)],
+            dies: vec![],
+        }
+    }
+
+    /// Finds the child of 'node' for 'element', or where a new
+    /// one would go in its list of children.
+    fn child(&self, node: usize, element: &Option<T>) -> Result<usize, usize> {
+        let hash = hash_element(element);
+        let children = &self.nodes[node].children;
+        let element_hash = |c: &u32| hash_element(&self.nodes[*c as usize].element);
+        let start = children.partition_point(|c| element_hash(c) < hash);
+        for (i, c) in children.iter().enumerate().skip(start) {
+            if element_hash(c) != hash {
+                return Err(i);
+            }
+            if self.nodes[*c as usize].element == *element {
+                return Ok(*c as usize);
+            }
+        }
+        Err(children.len())
+    }
+
+    /// Walks down from the root, newest element first.
+    fn find(&self, key: &[Option<T>]) -> Option<usize> {
+        key.iter()
+            .rev()
+            .try_fold(0, |node, e| self.child(node, e).ok())
+    }
+
+    fn find_or_insert(&mut self, key: &[Option<T>]) -> usize {
+        let mut node = 0;
+        for e in key.iter().rev() {
+            node = match self.child(node, e) {
+                Ok(child) => child,
+                Err(at) => {
+                    let child = index(self.nodes.len());
+                    self.nodes.push(Node::new(e.clone()));
+                    // Most nodes have few children, so lists grow
+                    // one at a time rather than keeping spare room.
+                    let children = &mut self.nodes[node].children;
+                    let mut grown = std::mem::take(children).into_vec();
+                    grown.insert(at, child);
+                    *children = grown.into_boxed_slice();
+                    child as usize
+                }
+            };
+        }
+        node
+    }
+
+    fn die(&self, node: usize) -> Option<&WeightedDie<T>> {
+        match self.nodes[node].die {
+            NONE => None,
+            d => Some(&self.dies[d as usize]),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &[Option<T>]) -> Option<&WeightedDie<T>> {
+        self.die(self.find(key)?)
+    }
+
+    /// Returns the die for 'key', adding an empty one if needed.
+    pub(crate) fn get_or_insert(&mut self, key: &[Option<T>]) -> &mut WeightedDie<T> {
+        let node = self.find_or_insert(key);
+        if self.nodes[node].die == NONE {
+            self.nodes[node].die = index(self.dies.len());
+            self.dies.push(WeightedDie::new());
+        }
+        &mut self.dies[self.nodes[node].die as usize]
+    }
+
+    /// Iterates over every context with a die.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Entry<'_, T>> + '_ {
+        Entries {
+            trie: self,
+            stack: vec![(0, vec![])],
+        }
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &WeightedDie<T>> + '_ {
+        self.dies.iter()
+    }
+
+    /// Returns every context with a die that ends with 'suffix',
+    /// including 'suffix' itself.
+    pub(crate) fn extensions(&self, suffix: &[Option<T>]) -> Vec<Entry<'_, T>> {
+        let stack = self
+            .find(suffix)
+            .map(|node| (node, suffix.to_vec()))
+            .into_iter()
+            .collect();
+        Entries { trie: self, stack }.collect()
+    }
+}
+
+/// Walks a trie depth first, keeping the key of every node
+/// still to visit.
+struct Entries<'a, T: Element> {
+    trie: &'a ContextTrie<T>,
+    stack: Vec<(usize, Vec<Option<T>>)>,
+}
+
+impl<'a, T: Element> Iterator for Entries<'a, T> {
+    type Item = Entry<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, key)) = self.stack.pop() {
+            for child in self.trie.nodes[node].children.iter() {
+                let child = *child as usize;
+                let mut longer = Vec::with_capacity(key.len() + 1);
+                longer.push(self.trie.nodes[child].element.clone());
+                longer.extend_from_slice(&key);
+                self.stack.push((child, longer));
+            }
+            if let Some(die) = self.trie.die(node) {
+                return Some((key, die));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rng::mix;
+    use super::*;
+    use std::collections::HashMap;
+    use std::mem::size_of;
+
+    fn key(s: &str) -> Vec<Option<char>> {
+        s.chars()
+            .map(|c| if c == '_' { None } else { Some(c) })
+            .collect()
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut t = ContextTrie::new();
+        t.get_or_insert(&key("abc")).modify('d', 2);
+        t.get_or_insert(&key("xbc")).modify('e', 1);
+        t.get_or_insert(&key("abc")).modify('f', 1);
+        t.get_or_insert(&key("_bc")).modify('d', 1);
+
+        assert_eq!(t.values().count(), 3);
+        assert_eq!(t.get(&key("abc")).unwrap().get_probability(&'d'), 2.0 / 3.0);
+        assert_eq!(t.get(&key("_bc")).unwrap().get_probability(&'d'), 1.0);
+        assert!(t.get(&key("bc")).is_none());
+        assert!(t.get(&key("zbc")).is_none());
+
+        // "bc" and "c" are shared by every context.
+        assert_eq!(t.nodes.len(), 1 + 2 + 3);
+
+        let mut keys: Vec<Vec<Option<char>>> = t.iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, vec![key("_bc"), key("abc"), key("xbc")]);
+    }
+
+    #[test]
+    fn empty_key() {
+        let mut t = ContextTrie::new();
+        assert!(t.get(&[]).is_none());
+        t.get_or_insert(&[]).modify(1u8, 1);
+        assert_eq!(t.get(&[]).unwrap().get_probability(&1), 1.0);
+        assert_eq!(t.iter().next().unwrap().0, vec![]);
+    }
+
+    #[test]
+    fn extensions() {
+        let mut t = ContextTrie::new();
+        for k in ["c", "bc", "abc", "xbc", "ac"].iter() {
+            t.get_or_insert(&key(k)).modify('z', 1);
+        }
+        let mut found: Vec<Vec<Option<char>>> = t
+            .extensions(&key("bc"))
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        found.sort();
+        assert_eq!(found, vec![key("abc"), key("bc"), key("xbc")]);
+        assert_eq!(t.extensions(&key("c")).len(), 5);
+        assert!(t.extensions(&key("q")).is_empty());
+    }
+
+    #[test]
+    fn smaller_than_a_hash_map() {
+        // Every context of up to four letters, as a
+        // VariableOrderChain keeps them, over random text.
+        let text: Vec<char> = (0..20000)
+            .map(|i| (b'a' + (mix(i) % 16) as u8) as char)
+            .collect();
+        let mut trie = ContextTrie::new();
+        let mut map = HashMap::new();
+        for i in 4..text.len() {
+            for len in 0..=4 {
+                let key: Vec<Option<char>> = text[i - len..i].iter().copied().map(Some).collect();
+                trie.get_or_insert(&key).modify(text[i], 1);
+                map.entry(key)
+                    .or_insert_with(WeightedDie::new)
+                    .modify(text[i], 1);
+            }
+        }
+        assert_eq!(trie.values().count(), map.len());
+
+        // Bytes outside the dies' own sides, leaving out what
+        // the allocator adds. A HashMap keeps a control byte
+        // for each of its buckets, and at most 7/8 are full.
+        let die = size_of::<WeightedDie<char>>();
+        let trie_bytes = trie.nodes.capacity() * size_of::<Node<char>>()
+            + trie
+                .nodes
+                .iter()
+                .map(|n| n.children.len() * 4)
+                .sum::<usize>()
+            + trie.dies.capacity() * die;
+        let buckets = (map.capacity() * 8 / 7).next_power_of_two();
+        let map_bytes = buckets * (size_of::<Vec<Option<char>>>() + die + 1)
+            + map.keys().map(|k| k.capacity() * 4).sum::<usize>();
+        assert!(
+            trie_bytes * 10 < map_bytes * 9,
+            "{} {}",
+            trie_bytes,
+            map_bytes
+        );
+    }
+}
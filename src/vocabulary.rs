@@ -0,0 +1,349 @@
+use cfg_if::cfg_if;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+
+#[cfg(feature = "serializer")]
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::MarkovChain;
+
+/// Interns values to compact u32 ids.
+///
+/// Ids are handed out in the order values are first seen,
+/// starting at 0. Chains over ids are much smaller than
+/// chains over large values, like String, which would be
+/// cloned into every context they appear in.
+///
+/// Each value is kept once, in id order. Lookups go through
+/// a table from the hash of a value to its id, and values
+/// whose hashes collide are chained together.
+///
+/// Serializes as the list of values, in id order.
+#[derive(Clone, Debug)]
+pub struct Vocabulary<K: Hash + Eq + Clone> {
+    items: Vec<K>,
+    // the newest id with each hash.
+    ids: HashMap<u64, u32>,
+    // the next older id with the same hash as each id, or NONE.
+    next: Vec<u32>,
+    hasher: RandomState,
+}
+
+/// Ends a chain of ids with the same hash.
+const NONE: u32 = u32::MAX;
+
+impl<K: Hash + Eq + Clone> Default for Vocabulary<K> {
+    fn default() -> Self {
+        Vocabulary {
+            items: vec![],
+            ids: HashMap::new(),
+            next: vec![],
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> PartialEq for Vocabulary<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl<K: Hash + Eq + Clone> Eq for Vocabulary<K> {}
+
+impl<K: Hash + Eq + Clone> Vocabulary<K> {
+    /// Creates an empty Vocabulary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id of 'item', adding it if it's new.
+    ///
+    /// Panics if there are already 2^32 - 1 values, since
+    /// the next one wouldn't have an id.
+    pub fn intern<Q>(&mut self, item: &Q) -> u32
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq + ToOwned<Owned = K>,
+    {
+        let hash = self.hasher.hash_one(item);
+        if let Some(id) = self.find(hash, item) {
+            return id;
+        }
+        let id = match u32::try_from(self.items.len()) {
+            Ok(id) if id != NONE => id,
+            _ => panic!("a vocabulary holds at most 2^32 - 1 values"),
+        };
+        self.items.push(item.to_owned());
+        self.next.push(self.ids.insert(hash, id).unwrap_or(NONE));
+        id
+    }
+
+    /// Returns the id of 'item', if it has been interned.
+    pub fn id<Q>(&self, item: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.find(self.hasher.hash_one(item), item)
+    }
+
+    /// Walks the chain of ids with 'hash', looking for 'item'.
+    fn find<Q>(&self, hash: u64, item: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq,
+    {
+        let mut id = *self.ids.get(&hash)?;
+        while id != NONE {
+            if self.items[id as usize].borrow() == item {
+                return Some(id);
+            }
+            id = self.next[id as usize];
+        }
+        None
+    }
+
+    /// Returns the value with id 'id'.
+    pub fn get(&self, id: u32) -> Option<&K> {
+        self.items.get(id as usize)
+    }
+
+    /// Returns the number of interned values.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns true if nothing has been interned.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Iterates over every interned value, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = &K> + '_ {
+        self.items.iter()
+    }
+}
+
+#[cfg(feature = "serializer")]
+impl<K: Hash + Eq + Clone + Serialize> Serialize for Vocabulary<K> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.items.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serializer")]
+impl<'de, K: Hash + Eq + Clone + Deserialize<'de>> Deserialize<'de> for Vocabulary<K> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut vocabulary = Vocabulary::new();
+        for item in Vec::<K>::deserialize(deserializer)? {
+            if vocabulary.id(&item).is_some() {
+                return Err(D::Error::custom("duplicate vocabulary entry"));
+            }
+            vocabulary.intern(&item);
+        }
+        Ok(vocabulary)
+    }
+}
+
+/// A MarkovChain over any Hash + Eq + Clone type, storing
+/// each value only once.
+///
+/// Values are interned into a Vocabulary, and the chain
+/// itself only ever sees their u32 ids. Views that contain
+/// values the chain was never trained on can't match any
+/// context, so they generate None.
+#[derive(Clone)]
+#[cfg_attr(feature = "serializer", derive(Debug))]
+#[cfg_attr(feature = "serializer", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serializer",
+    serde(bound(serialize = "K: Serialize", deserialize = "K: Deserialize<'de>"))
+)]
+pub struct InternedChain<K: Hash + Eq + Clone> {
+    vocabulary: Vocabulary<K>,
+    chain: MarkovChain<u32>,
+}
+
+impl<K: Hash + Eq + Clone> PartialEq for InternedChain<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.vocabulary == other.vocabulary && self.chain == other.chain
+    }
+}
+
+impl<K: Hash + Eq + Clone> InternedChain<K> {
+    /// Creates a new InternedChain.
+    /// See MarkovChain::new for the parameters.
+    pub fn new(order: usize, optional_elements: &[usize]) -> Self {
+        InternedChain {
+            vocabulary: Vocabulary::new(),
+            chain: MarkovChain::new(order, optional_elements),
+        }
+    }
+
+    /// Returns the values this chain has seen.
+    pub fn vocabulary(&self) -> &Vocabulary<K> {
+        &self.vocabulary
+    }
+
+    /// Returns the underlying chain, which works in ids.
+    pub fn chain(&self) -> &MarkovChain<u32> {
+        &self.chain
+    }
+
+    /// Feeds training data into the model.
+    /// See MarkovChain::train.
+    pub fn train(&mut self, view: &[K], result: &K, weight_delta: i32) {
+        let view: Vec<u32> = view.iter().map(|k| self.vocabulary.intern(k)).collect();
+        let result = self.vocabulary.intern(result);
+        self.chain.train(&view, result, weight_delta);
+    }
+
+    /// Looks up the ids of a view, or None if any of its
+    /// values are unknown.
+    fn ids(&self, view: &[K]) -> Option<Vec<u32>> {
+        view.iter().map(|k| self.vocabulary.id(k)).collect()
+    }
+
+    /// Looks up the ids of a partial view, or None if any of
+    /// its known values are unknown to the vocabulary.
+    fn partial_ids(&self, view: &[Option<K>]) -> Option<Vec<Option<u32>>> {
+        view.iter()
+            .map(|k| match k {
+                Some(k) => self.vocabulary.id(k).map(Some),
+                None => Some(None),
+            })
+            .collect()
+    }
+
+    fn value(&self, id: Option<u32>) -> Option<&K> {
+        id.and_then(|id| self.vocabulary.get(id))
+    }
+
+    /// Generates the next value, given the previous item(s).
+    /// See MarkovChain::generate_deterministic.
+    pub fn generate_deterministic(&self, view: &[K], rand_val: u64) -> Option<&K> {
+        let view = self.ids(view)?;
+        self.value(self.chain.generate_deterministic(&view, rand_val))
+    }
+
+    /// Generates the next value, given the previous item(s).
+    /// See MarkovChain::generate_deterministic_from_partial.
+    pub fn generate_deterministic_from_partial(
+        &self,
+        view: &[Option<K>],
+        rand_val: u64,
+    ) -> Option<&K> {
+        let view = self.partial_ids(view)?;
+        self.value(
+            self.chain
+                .generate_deterministic_from_partial(&view, rand_val),
+        )
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "rand")] {
+            /// Generates the next value, given the previous item(s).
+            /// See MarkovChain::generate.
+            pub fn generate(&self, view: &[K]) -> Option<&K> {
+                let view = self.ids(view)?;
+                self.value(self.chain.generate(&view))
+            }
+
+            /// Generates the next value, given the previous item(s).
+            /// See MarkovChain::generate_from_partial.
+            pub fn generate_from_partial(&self, view: &[Option<K>]) -> Option<&K> {
+                let view = self.partial_ids(view)?;
+                self.value(self.chain.generate_from_partial(&view))
+            }
+        }
+    }
+
+    /// Returns the probability of getting 'result', given
+    /// 'view'.
+    pub fn probability(&self, view: &[Option<K>], result: &K) -> f32 {
+        match (self.partial_ids(view), self.vocabulary.id(result)) {
+            (Some(view), Some(result)) => self.chain.probability(&view, &result),
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns() {
+        let mut v: Vocabulary<String> = Vocabulary::new();
+        assert_eq!(v.intern("a"), 0);
+        assert_eq!(v.intern("b"), 1);
+        assert_eq!(v.intern("a"), 0);
+        assert_eq!(v.len(), 2);
+        assert_eq!(v.id("b"), Some(1));
+        assert_eq!(v.id("c"), None);
+        assert_eq!(v.get(1).map(String::as_str), Some("b"));
+        assert_eq!(v.get(2), None);
+        let items: Vec<&String> = v.iter().collect();
+        assert_eq!(items, vec!["a", "b"]);
+    }
+
+    /// Values that all hash the same.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Clash(u8);
+
+    impl Hash for Clash {
+        fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+    }
+
+    #[test]
+    fn hash_collisions() {
+        let mut v = Vocabulary::new();
+        for i in 0..4 {
+            assert_eq!(v.intern(&Clash(i)), i as u32);
+        }
+        for i in 0..4 {
+            assert_eq!(v.intern(&Clash(i)), i as u32);
+            assert_eq!(v.id(&Clash(i)), Some(i as u32));
+        }
+        assert_eq!(v.id(&Clash(9)), None);
+        assert_eq!(v.len(), 4);
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    enum Note {
+        Rest,
+        Pitch(String),
+    }
+
+    #[test]
+    fn non_copy_values() {
+        let c = Note::Pitch("C".into());
+        let e = Note::Pitch("E".into());
+        let (after_c, after_e) = ([c.clone()], [e.clone()]);
+        let mut m = InternedChain::new(1, &[0]);
+        m.train(&after_c, &e, 2);
+        m.train(&after_c, &Note::Rest, 1);
+        m.train(&after_e, &c, 1);
+
+        assert_eq!(m.vocabulary().len(), 3);
+        assert_eq!(m.generate_deterministic(&after_c, 0), Some(&e));
+        assert_eq!(m.generate_deterministic(&after_c, 2), Some(&Note::Rest));
+        assert_eq!(m.generate_deterministic(&after_e, 0), Some(&c));
+        assert_eq!(
+            m.generate_deterministic(&[Note::Pitch("G".into())], 0),
+            None
+        );
+
+        assert_eq!(m.probability(&[Some(c.clone())], &e), 2.0 / 3.0);
+        assert_eq!(m.probability(&[None], &c), 0.25);
+        assert_eq!(
+            m.probability(&[Some(c.clone())], &Note::Pitch("G".into())),
+            0.0
+        );
+        assert_eq!(m.generate_deterministic_from_partial(&[None], 3), Some(&c));
+    }
+}
@@ -0,0 +1,276 @@
+use cfg_if::cfg_if;
+
+use super::die::resolve_roll;
+use super::trie::ContextTrie;
+use super::Element;
+
+/// A variable-order Markov model.
+///
+/// Every context length from 0 up to max_order is trained,
+/// and each prediction starts from the longest context that
+/// has been seen at least min_support times. From there, it
+/// falls back to shorter contexts with the PPM-C escape
+/// mechanism: a context that has seen 'd' distinct outcomes
+/// over 'n' samples escapes with probability d / (n + d),
+/// and outcomes that the longer context already offered are
+/// excluded from the shorter one. The empty context never
+/// escapes, so anything seen at all can be generated.
+///
+/// Contexts are kept in a trie, so every context shares
+/// storage with the shorter ones it ends with.
+#[derive(Clone)]
+pub struct VariableOrderChain<T: Element> {
+    max_order: usize,
+    min_support: u64,
+    contexts: ContextTrie<T>,
+}
+
+impl<T: Element> VariableOrderChain<T> {
+    /// Creates a new VariableOrderChain that looks back at
+    /// most 'max_order' elements.
+    pub fn new(max_order: usize) -> Self {
+        VariableOrderChain {
+            max_order,
+            min_support: 1,
+            contexts: ContextTrie::new(),
+        }
+    }
+
+    /// Only starts predictions from contexts that have been
+    /// trained with at least this much weight. Rare, long
+    /// contexts tend to overfit. The default is 1.
+    pub fn min_support(mut self, min_support: u64) -> Self {
+        self.min_support = min_support.max(1);
+        self
+    }
+
+    /// Returns the longest context this chain looks at.
+    pub fn max_order(&self) -> usize {
+        self.max_order
+    }
+
+    /// Keeps the last 'len' elements of the view.
+    fn key(view: &[T], len: usize) -> Vec<Option<T>> {
+        view[view.len() - len..]
+            .iter()
+            .map(|e| Some(e.clone()))
+            .collect()
+    }
+
+    /// Feeds training data into the model.
+    ///
+    /// 'view' is the sliding window of the latest elements.
+    /// Only the last max_order elements are looked at, and
+    /// shorter views are fine, like at the start of a
+    /// sequence. 'result' is the element that followed them.
+    pub fn train(&mut self, view: &[T], result: T, weight_delta: i32) {
+        for len in 0..=self.max_order.min(view.len()) {
+            self.contexts
+                .get_or_insert(&Self::key(view, len))
+                .modify(result.clone(), weight_delta);
+        }
+    }
+
+    /// Returns the length of the longest context of 'view'
+    /// with enough support to predict from.
+    pub fn context_order(&self, view: &[T]) -> usize {
+        (0..=self.max_order.min(view.len()))
+            .rev()
+            .find(|len| {
+                self.contexts
+                    .get(&Self::key(view, *len))
+                    .is_some_and(|d| d.total_weight() >= self.min_support)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Calls 'visit' with the live sides of each context of
+    /// 'view', longest first, leaving out sides that a longer
+    /// context already offered, along with the weight of
+    /// escaping to the next shorter context. That weight is 0
+    /// when no shorter context has a side left. Stops when
+    /// 'visit' returns Some.
+    ///
+    /// With 'escape_root', the empty context can escape too,
+    /// for callers that have a model below it.
+    pub(crate) fn walk<R>(
+        &self,
+        view: &[T],
+        escape_root: bool,
+        mut visit: impl FnMut(&[(T, u64)], u64) -> Option<R>,
+    ) -> Option<R> {
+        let mut excluded: Vec<T> = vec![];
+        for len in (0..=self.context_order(view)).rev() {
+            let die = match self.contexts.get(&Self::key(view, len)) {
+                Some(d) => d,
+                None => continue,
+            };
+            let sides: Vec<(T, u64)> = die
+                .iter()
+                .filter(|(e, w)| *w > 0 && !excluded.contains(e))
+                .collect();
+            if sides.is_empty() {
+                continue;
+            }
+            // PPM-C: escape weight is the number of distinct
+            // outcomes, unless there's nowhere left to go.
+            let offered = |e: &T| excluded.contains(e) || sides.iter().any(|s| s.0 == *e);
+            let shorter = (0..len).any(|l| {
+                self.contexts
+                    .get(&Self::key(view, l))
+                    .is_some_and(|d| d.iter().any(|(e, w)| w > 0 && !offered(&e)))
+            });
+            let escape = match escape_root || shorter {
+                true => sides.len() as u64,
+                false => 0,
+            };
+            if let Some(found) = visit(&sides, escape) {
+                return Some(found);
+            }
+            excluded.extend(sides.iter().map(|s| s.0.clone()));
+        }
+        None
+    }
+
+    /// Picks the next value, taking each roll from 'roll'
+    /// given the total weight to roll against.
+    fn predict(&self, view: &[T], mut roll: impl FnMut(u64) -> u64) -> Option<T> {
+        self.walk(view, false, |sides, escape| {
+            let mut r = roll(sides.iter().map(|s| s.1).sum::<u64>() + escape);
+            for (e, w) in sides {
+                if r < *w {
+                    return Some(e.clone());
+                }
+                r -= w;
+            }
+            None
+        })
+    }
+
+    /// Generates the next value, given the previous item(s).
+    ///
+    /// view is the sliding window of the latest elements.
+    /// only the last max_order elements are looked at.
+    ///
+    /// rand_val allows for a deterministic result, if supplied.
+    /// Each escape to a shorter context uses up part of it,
+    /// like the digits of a number.
+    pub fn generate_deterministic(&self, view: &[T], rand_val: u64) -> Option<T> {
+        let mut rand_val = rand_val;
+        self.predict(view, |total| {
+            let r = resolve_roll(total, Some(rand_val));
+            rand_val /= total;
+            r
+        })
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "rand")] {
+            /// Generates the next value, given the previous item(s).
+            ///
+            /// view is the sliding window of the latest elements.
+            /// only the last max_order elements are looked at.
+            pub fn generate(&self, view: &[T]) -> Option<T> {
+                self.predict(view, |total| resolve_roll(total, None))
+            }
+        }
+    }
+
+    /// Returns the probability of getting 'result', given
+    /// 'view', including the chance of escaping to shorter
+    /// contexts first.
+    pub fn probability(&self, view: &[T], result: &T) -> f32 {
+        let mut reach = 1.0;
+        self.walk(view, false, |sides, escape| {
+            let n: u64 = sides.iter().map(|s| s.1).sum();
+            let total = (n + escape) as f64;
+            match sides.iter().find(|s| s.0 == *result) {
+                Some((_, w)) => Some(reach * *w as f64 / total),
+                None => {
+                    reach *= escape as f64 / total;
+                    None
+                }
+            }
+        })
+        .unwrap_or(0.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn train(m: &mut VariableOrderChain<char>, text: &str) {
+        let text: Vec<char> = text.chars().collect();
+        for i in 0..text.len() {
+            m.train(&text[..i], text[i], 1);
+        }
+    }
+
+    #[test]
+    fn picks_longest_supported_context() {
+        let mut m = VariableOrderChain::new(3);
+        train(&mut m, "abracadabra");
+        let view: Vec<char> = "abr".chars().collect();
+        assert_eq!(m.context_order(&view), 3);
+        // "abr" was always followed by 'a'.
+        for roll in 0..2 {
+            assert_eq!(m.generate_deterministic(&view, roll), Some('a'));
+        }
+        // "zbr" was never seen, but "br" was.
+        assert_eq!(m.context_order(&['z', 'b', 'r']), 2);
+        assert_eq!(m.context_order(&['z', 'z']), 0);
+
+        let mut m = VariableOrderChain::new(3).min_support(3);
+        train(&mut m, "abracadabra");
+        assert_eq!(m.context_order(&view), 0);
+        assert_eq!(m.context_order(&['a']), 1);
+    }
+
+    #[test]
+    fn ppm_c_escape() {
+        let mut m = VariableOrderChain::new(1);
+        train(&mut m, "abacab");
+        // After 'a': b twice, c once. Escape is 2 / (3 + 2).
+        // The empty context, without b and c: a three times.
+        assert_eq!(m.probability(&['a'], &'b'), 2.0 / 5.0);
+        assert_eq!(m.probability(&['a'], &'c'), 1.0 / 5.0);
+        assert_eq!(m.probability(&['a'], &'a'), 2.0 / 5.0);
+        assert_eq!(m.probability(&['a'], &'z'), 0.0);
+
+        // Rolls 0..3 land on a context side, and 3 and 4
+        // escape to the empty context.
+        let rolls: Vec<Option<char>> = (0..5)
+            .map(|r| m.generate_deterministic(&['a'], r))
+            .collect();
+        assert_eq!(
+            rolls,
+            vec![Some('b'), Some('b'), Some('c'), Some('a'), Some('a')]
+        );
+
+        let total: f32 = "abc".chars().map(|e| m.probability(&['b'], &e)).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn no_escape_without_new_sides() {
+        let mut m = VariableOrderChain::new(1);
+        train(&mut m, "aab");
+        // After 'a': a once, b once. The empty context has
+        // nothing else, so there's no escape.
+        assert_eq!(m.probability(&['a'], &'a'), 0.5);
+        assert_eq!(m.probability(&['a'], &'b'), 0.5);
+        let rolls: Vec<Option<char>> = (0..4)
+            .map(|r| m.generate_deterministic(&['a'], r))
+            .collect();
+        assert_eq!(rolls, vec![Some('a'), Some('b'), Some('a'), Some('b')]);
+    }
+
+    #[test]
+    fn empty() {
+        let m: VariableOrderChain<u8> = VariableOrderChain::new(2);
+        assert_eq!(m.generate_deterministic(&[1, 2], 0), None);
+        assert_eq!(m.probability(&[1], &1), 0.0);
+        assert_eq!(m.context_order(&[1, 2]), 0);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::die::WeightedDie;
+use super::frozen::hash_key;
+use super::storage::{ContextMap, Entry};
+use super::Element;
+
+/// How a MarkovChain answers views with unknown (None)
+/// elements.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serializer", derive(serde::Serialize, serde::Deserialize))]
+pub enum Indexing {
+    /// Every training sample is stored under all 2^k
+    /// combinations of its optional elements being known
+    /// or unknown. Lookups are a single map access, but
+    /// memory and training time grow by 2^k.
+    #[default]
+    Expanded,
+    /// Only full contexts are stored. A view with None
+    /// elements is answered by adding up the dies of every
+    /// context that agrees with its known elements. As with
+    /// Expanded, only optional elements can be None, and
+    /// views with None anywhere else match nothing.
+    ///
+    /// Aggregated dies are cached until the next time the
+    /// chain is trained. The cache holds up to 4096 views,
+    /// and starts over when it fills up.
+    Wildcard,
+}
+
+/// Aggregated dies for partial views, keyed by view.
+/// None means that no trained context matched.
+type Aggregates<T> = HashMap<Vec<Option<T>>, Option<Arc<WeightedDie<T>>>>;
+
+/// The most views a QueryCache holds at once.
+const CACHE_LIMIT: usize = 4096;
+
+pub(crate) struct QueryCache<T: Element>(Mutex<Aggregates<T>>);
+
+impl<T: Element> Default for QueryCache<T> {
+    fn default() -> Self {
+        QueryCache(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<T: Element> QueryCache<T> {
+    pub(crate) fn clear(&mut self) {
+        self.0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Returns the cached die for 'key', computing it with
+    /// 'aggregate' the first time.
+    pub(crate) fn get_or_insert_with<F>(
+        &self,
+        key: &[Option<T>],
+        aggregate: F,
+    ) -> Option<Arc<WeightedDie<T>>>
+    where
+        F: FnOnce() -> Option<WeightedDie<T>>,
+    {
+        if let Some(found) = self.lock().get(key) {
+            return found.clone();
+        }
+        // The lock isn't held while aggregating, so two threads
+        // may do the same work. They always get the same die.
+        let die = aggregate().map(Arc::new);
+        let mut cache = self.lock();
+        if cache.len() >= CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(key.to_vec(), die.clone());
+        die
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Aggregates<T>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// A cache is only an optimization, so clones start empty
+// and every cache is equal to every other one.
+impl<T: Element> Clone for QueryCache<T> {
+    fn clone(&self) -> Self {
+        QueryCache::default()
+    }
+}
+
+impl<T: Element> PartialEq for QueryCache<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<T: Element> std::fmt::Debug for QueryCache<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "QueryCache")
+    }
+}
+
+fn matches<T: Element>(pattern: &[Option<T>], key: &[Option<T>]) -> bool {
+    pattern.len() == key.len()
+        && pattern
+            .iter()
+            .zip(key.iter())
+            .all(|(p, k)| p.is_none() || p == k)
+}
+
+/// Adds 'die' into 'into', side by side.
+fn merge<T: Element>(into: &mut WeightedDie<T>, die: &WeightedDie<T>) {
+    for (e, weight) in die.iter() {
+        into.add_weight(e, weight);
+    }
+}
+
+/// Returns every trained context in 'map', sorted by hash.
+/// Merging dies in this order keeps rolls the same from run
+/// to run, even though maps iterate in a random order.
+fn sorted_contexts<T: Element>(map: &ContextMap<T>) -> Vec<Entry<'_, T>> {
+    let mut contexts: Vec<_> = map.iter().filter(|(_, d)| d.total_weight() > 0).collect();
+    contexts.sort_by_cached_key(|(k, _)| hash_key(k));
+    contexts
+}
+
+/// Adds up the dies of every context in 'map' that agrees
+/// with the known elements of 'pattern'.
+pub(crate) fn aggregate<T: Element>(
+    map: &ContextMap<T>,
+    pattern: &[Option<T>],
+) -> Option<WeightedDie<T>> {
+    // Matching contexts all end with whatever follows the last
+    // unknown element, which narrows the search with a trie.
+    let known_tail = match pattern.iter().rposition(|e| e.is_none()) {
+        Some(i) => &pattern[i + 1..],
+        None => pattern,
+    };
+    let mut found: Vec<_> = map
+        .extensions(known_tail)
+        .into_iter()
+        .filter(|(k, d)| d.total_weight() > 0 && matches(pattern, k))
+        .collect();
+    if found.is_empty() {
+        return None;
+    }
+    found.sort_by_cached_key(|(k, _)| hash_key(k));
+
+    let mut die = WeightedDie::new();
+    for (_, d) in found {
+        merge(&mut die, d);
+    }
+    Some(die)
+}
+
+/// Builds the map an Expanded chain would have, given the
+/// full contexts of a Wildcard chain. Partial contexts get
+/// their sides in the same order that aggregate gives them,
+/// so both roll the same way.
+pub(crate) fn expand<T: Element>(
+    map: &ContextMap<T>,
+    optional_elements: &[usize],
+) -> ContextMap<T> {
+    let mut expanded = ContextMap::new(map.storage());
+    for (key, die) in sorted_contexts(map) {
+        for mask in 0..(1usize << optional_elements.len()) {
+            let mut partial = key.clone();
+            for (bit, i) in optional_elements.iter().enumerate() {
+                if mask & (1 << bit) != 0 {
+                    partial[*i] = None;
+                }
+            }
+            merge(expanded.get_or_insert(partial), die);
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
+
+    fn key(s: &str) -> Vec<Option<char>> {
+        s.chars()
+            .map(|c| if c == '_' { None } else { Some(c) })
+            .collect()
+    }
+
+    #[test]
+    fn aggregates_matches() {
+        for storage in [Storage::HashMap, Storage::Trie].iter() {
+            let mut map = ContextMap::new(*storage);
+            map.get_or_insert(key("abc")).modify('x', 2);
+            map.get_or_insert(key("zbc")).modify('y', 1);
+            map.get_or_insert(key("abd")).modify('x', 1);
+            map.get_or_insert(key("qqc")).modify('x', 5);
+
+            let die = aggregate(&map, &key("_bc")).unwrap();
+            assert_eq!(die.get_probability(&'x'), 2.0 / 3.0);
+            assert_eq!(die.get_probability(&'y'), 1.0 / 3.0);
+
+            let die = aggregate(&map, &key("a__")).unwrap();
+            assert_eq!(die.get_probability(&'x'), 1.0);
+            assert_eq!(die.total_weight(), 3);
+
+            assert_eq!(aggregate(&map, &key("___")).unwrap().total_weight(), 9);
+            assert!(aggregate(&map, &key("_bq")).is_none());
+        }
+    }
+
+    #[test]
+    fn cache_is_capped() {
+        let cache = QueryCache::default();
+        for i in 0..CACHE_LIMIT as u32 + 10 {
+            cache.get_or_insert_with(&[Some(i), None], || None);
+        }
+        assert_eq!(cache.lock().len(), 10);
+        assert!(cache
+            .lock()
+            .contains_key(&vec![Some(CACHE_LIMIT as u32 + 9), None]));
+    }
+
+    #[test]
+    fn expand_matches_aggregate() {
+        let mut map = ContextMap::new(Storage::HashMap);
+        map.get_or_insert(key("ab")).modify('x', 2);
+        map.get_or_insert(key("cb")).modify('y', 1);
+        map.get_or_insert(key("cd")).modify('x', 1);
+
+        let expanded = expand(&map, &[0]);
+        assert_eq!(expanded.values().count(), 5);
+        for pattern in ["_b", "_d", "ab"].iter() {
+            let p = key(pattern);
+            let a = aggregate(&map, &p).unwrap();
+            let e = expanded.get(&p).unwrap();
+            assert!(a == *e);
+            for roll in 0..a.total_weight() {
+                assert_eq!(a.roll(Some(roll)), e.roll(Some(roll)));
+            }
+        }
+        assert!(expanded.get(&key("a_")).is_none());
+    }
+
+    #[test]
+    fn cache() {
+        let cache: QueryCache<char> = QueryCache::default();
+        let mut calls = 0;
+        for _ in 0..3 {
+            cache.get_or_insert_with(&key("_a"), || {
+                calls += 1;
+                None
+            });
+        }
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,503 @@
+//! Endless worlds, generated a chunk at a time.
+//!
+//! The world is cut into chunks along seam lines: every
+//! chunk's top row and left column. Around each seam is a
+//! band of every cell within reach of it, and bands are
+//! generated first, the same way whichever chunk asks for
+//! them. Blocks around the corners, where seams cross, come
+//! first, then the bands across and down from one block to
+//! the next. Each is generated once per chunk. Finally a chunk's inside is filled in between the four
+//! bands around it, so chunks on either side of a seam both
+//! match it, and every cell fits all of its neighbors.
+//!
+//! Blocks and bands are generated as if the world ended just
+//! past them. If the model's border is also a cell it has
+//! learned, like empty space in a tile map, they're ringed
+//! with it, so whatever is later generated next to them only
+//! has to fit the border, as the edge of a map of its own
+//! would.
+//!
+//! Every roll is a hash of the world seed and the coordinates
+//! of the cell, so a chunk comes out the same every time, in
+//! any order.
+
+use super::grid::{GenerateOptions, Grid, GridError, GridModel, Policy};
+use super::rng::{mix, SplitMix64};
+use super::topology::{visit_order, Lattice, Topology};
+use super::Element;
+
+/// A box of cells cut out of the world. Neighbors outside it
+/// are border if it's bounded, and unknown otherwise.
+struct Window {
+    lattice: Lattice,
+    bounded: bool,
+}
+
+impl Topology for Window {
+    fn dims(&self) -> Vec<usize> {
+        self.lattice.dims()
+    }
+
+    fn neighbors(&self, cell: usize) -> Vec<Option<usize>> {
+        self.lattice.neighbors(cell)
+    }
+
+    fn bounded(&self) -> bool {
+        self.bounded
+    }
+}
+
+/// A box of generated cells, whose top left cell is at
+/// (x, y) in the world.
+struct Patch<T> {
+    x: i64,
+    y: i64,
+    width: usize,
+    cells: Vec<T>,
+}
+
+impl<T: Clone> Patch<T> {
+    /// Copies every cell that's also in 'into' over to it.
+    fn paste(&self, into: &mut Patch<Option<T>>) {
+        let h = into.cells.len() / into.width;
+        for (i, v) in self.cells.iter().enumerate() {
+            let dx = self
+                .x
+                .wrapping_add((i % self.width) as i64)
+                .wrapping_sub(into.x) as u64;
+            let dy = self
+                .y
+                .wrapping_add((i / self.width) as i64)
+                .wrapping_sub(into.y) as u64;
+            if dx < into.width as u64 && dy < h as u64 {
+                into.cells[dy as usize * into.width + dx as usize] = Some(v.clone());
+            }
+        }
+    }
+}
+
+/// Generates the chunks of an endless 2D world.
+#[derive(Clone)]
+pub struct ChunkedWorld<T: Element> {
+    model: GridModel<T>,
+    width: usize,
+    height: usize,
+    seed: u64,
+    options: GenerateOptions,
+}
+
+impl<T: Element> ChunkedWorld<T> {
+    /// Creates a world of 'width' by 'height' chunks, drawn
+    /// from 'model'.
+    ///
+    /// Dead ends are backed out of for up to 100,000 steps per
+    /// block, band and chunk. See options.
+    ///
+    /// Panics unless both sizes are more than four times as
+    /// far as the model's neighborhood reaches.
+    pub fn new(model: GridModel<T>, width: usize, height: usize, seed: u64) -> Self {
+        let world = ChunkedWorld {
+            model,
+            width,
+            height,
+            seed,
+            options: GenerateOptions::new().policy(Policy::Backtrack(100_000)),
+        };
+        let k = world.reach();
+        assert!(
+            width > 4 * k && height > 4 * k,
+            "chunks are too small to keep their seams apart"
+        );
+        world
+    }
+
+    /// Sets how bands and chunks are filled in. The seed in
+    /// 'options' is ignored: the world seed is used instead.
+    pub fn options(mut self, options: GenerateOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the underlying model.
+    pub fn model(&self) -> &GridModel<T> {
+        &self.model
+    }
+
+    /// Returns the width and height of a chunk.
+    pub fn chunk_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the world seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Generates the chunk at ('cx', 'cy'), which covers the
+    /// world from (cx * width, cy * height).
+    ///
+    /// A Contradiction gives the (x, y) of the cell counted
+    /// from three reaches above and to the left of the chunk,
+    /// which takes in every block and band it depends on.
+    pub fn chunk(&self, cx: i64, cy: i64) -> Result<Grid<T>, GridError> {
+        let (w, h, k) = (self.width, self.height, self.reach());
+        let x0 = cx.wrapping_mul(w as i64);
+        let y0 = cy.wrapping_mul(h as i64);
+        let at = (x0.wrapping_sub(3 * k as i64), y0.wrapping_sub(3 * k as i64));
+        let below = y0.wrapping_add(h as i64);
+        let right = x0.wrapping_add(w as i64);
+
+        let top_left = self.block(x0, y0, at)?;
+        let top_right = self.block(right, y0, at)?;
+        let bottom_left = self.block(x0, below, at)?;
+        let bottom_right = self.block(right, below, at)?;
+        let bands = [
+            self.across(x0, y0, [&top_left, &top_right], at)?,
+            self.across(x0, below, [&bottom_left, &bottom_right], at)?,
+            self.down(x0, y0, [&top_left, &bottom_left], at)?,
+            self.down(right, y0, [&top_right, &bottom_right], at)?,
+        ];
+        let (left, top) = (x0.wrapping_sub(k as i64), y0.wrapping_sub(k as i64));
+        let (ww, wh) = (w + 2 * k + 1, h + 2 * k + 1);
+        let mut known = Patch {
+            x: left,
+            y: top,
+            width: ww,
+            cells: vec![None; ww * wh],
+        };
+        for band in &bands {
+            band.paste(&mut known);
+        }
+        let filled = self.fill_window(known, false, at)?;
+        let mut chunk = Grid::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                chunk.set(x, y, Some(filled.cells[(y + k) * ww + x + k].clone()));
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Returns how many cells away the neighborhood reaches.
+    fn reach(&self) -> usize {
+        self.model
+            .offsets()
+            .iter()
+            .map(|(dx, dy)| dx.unsigned_abs().max(dy.unsigned_abs()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Generates the block around the corner (x, y): every cell
+    /// within one reach of it up or down, and two reaches left
+    /// or right, where the bands across from it overlap. That
+    /// also takes in every cell of the bands across that a
+    /// band down from it sees.
+    fn block(&self, x: i64, y: i64, at: (i64, i64)) -> Result<Patch<T>, GridError> {
+        let k = self.reach();
+        let (left, top) = (x.wrapping_sub(2 * k as i64), y.wrapping_sub(k as i64));
+        self.enclose(left, top, 4 * k + 1, 2 * k + 1, &[], at)
+    }
+
+    /// Generates the band across from the corner (x, y) to the
+    /// next corner, between the 'blocks' around both.
+    fn across(
+        &self,
+        x: i64,
+        y: i64,
+        blocks: [&Patch<T>; 2],
+        at: (i64, i64),
+    ) -> Result<Patch<T>, GridError> {
+        let k = self.reach();
+        let (left, top) = (x.wrapping_sub(2 * k as i64), y.wrapping_sub(k as i64));
+        self.enclose(left, top, self.width + 4 * k + 1, 2 * k + 1, &blocks, at)
+    }
+
+    /// Generates the band down from the corner (x, y) to the
+    /// next corner, between the 'blocks' around both.
+    fn down(
+        &self,
+        x: i64,
+        y: i64,
+        blocks: [&Patch<T>; 2],
+        at: (i64, i64),
+    ) -> Result<Patch<T>, GridError> {
+        let k = self.reach();
+        let (left, top) = (x.wrapping_sub(k as i64), y.wrapping_sub(k as i64));
+        self.enclose(left, top, 2 * k + 1, self.height + 2 * k + 1, &blocks, at)
+    }
+
+    /// Generates the 'w' by 'h' box at (x, y), around the cells
+    /// of 'known' that fall in it.
+    ///
+    /// If the model has a border it has also learned as a
+    /// cell, like empty space, the box is generated inside a
+    /// ring of border cells one reach deep, past which the
+    /// world ends. Then every cell around the box, where
+    /// another box or a chunk will go, is known to fit the
+    /// border. Cells of 'known' that fall in the ring are kept.
+    fn enclose(
+        &self,
+        x: i64,
+        y: i64,
+        w: usize,
+        h: usize,
+        known: &[&Patch<T>],
+        at: (i64, i64),
+    ) -> Result<Patch<T>, GridError> {
+        let border = self.ring();
+        let ring = match border {
+            Some(_) => self.reach(),
+            None => 0,
+        };
+        let (left, top) = (x.wrapping_sub(ring as i64), y.wrapping_sub(ring as i64));
+        let (ww, wh) = (w + 2 * ring, h + 2 * ring);
+        let mut cells = Patch {
+            x: left,
+            y: top,
+            width: ww,
+            cells: vec![None; ww * wh],
+        };
+        for patch in known {
+            patch.paste(&mut cells);
+        }
+        for (i, cell) in cells.cells.iter_mut().enumerate() {
+            let (cx, cy) = (i % ww, i / ww);
+            let inside = (ring..ring + w).contains(&cx) && (ring..ring + h).contains(&cy);
+            if !inside && cell.is_none() {
+                *cell = border.cloned();
+            }
+        }
+        let filled = self.fill_window(cells, true, at)?;
+        Ok(Patch {
+            x,
+            y,
+            width: w,
+            cells: filled
+                .cells
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| {
+                    let (cx, cy) = (i % ww, i / ww);
+                    (ring..ring + w).contains(&cx) && (ring..ring + h).contains(&cy)
+                })
+                .map(|(_, v)| v)
+                .collect(),
+        })
+    }
+
+    /// Returns the model's border, if it has also learned it
+    /// as a cell.
+    fn ring(&self) -> Option<&T> {
+        let border = self.model.topology().border_value()?;
+        let anything = vec![None; self.model.offsets().len()];
+        match self.model.chain().probability(&anything, border) > 0.0 {
+            true => Some(border),
+            false => None,
+        }
+    }
+
+    /// Fills in the empty cells of 'known', where neighbors
+    /// outside it are border if it's 'bounded'. The n-th roll
+    /// at a cell hashes its world coordinates and n. A
+    /// Contradiction is counted from 'at'.
+    fn fill_window(
+        &self,
+        known: Patch<Option<T>>,
+        bounded: bool,
+        at: (i64, i64),
+    ) -> Result<Patch<T>, GridError> {
+        let (x0, y0, w) = (known.x, known.y, known.width);
+        let h = known.cells.len() / w;
+        let offsets = self
+            .model
+            .offsets()
+            .iter()
+            .map(|(dx, dy)| vec![*dx, *dy])
+            .collect();
+        let window = Window {
+            lattice: Lattice::new(&[w, h], offsets),
+            bounded,
+        };
+        let mut rng = SplitMix64::new(self.hash(&[x0 as u64, y0 as u64, w as u64, h as u64]));
+        let order = visit_order(&window, self.options.traversal, &mut rng);
+
+        let mut rolled = vec![0; w * h];
+        let mut roll = |cell: usize| {
+            rolled[cell] += 1;
+            let x = x0.wrapping_add((cell % w) as i64);
+            let y = y0.wrapping_add((cell / w) as i64);
+            self.hash(&[x as u64, y as u64, rolled[cell]])
+        };
+        let filled = self.model.topology().fill_in_order(
+            &window,
+            &known.cells,
+            &self.options,
+            &order,
+            &mut roll,
+            &|_| true,
+        );
+        match filled {
+            Ok(cells) => Ok(Patch {
+                x: x0,
+                y: y0,
+                width: w,
+                cells,
+            }),
+            Err(e) => Err(e.map_cell(|c| {
+                let x = x0.wrapping_add(c[0] as i64).wrapping_sub(at.0);
+                let y = y0.wrapping_add(c[1] as i64).wrapping_sub(at.1);
+                (x as usize, y as usize)
+            })),
+        }
+    }
+
+    fn hash(&self, values: &[u64]) -> u64 {
+        values.iter().fold(self.seed, |h, v| {
+            mix((h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grid::{Neighborhood, Symmetry};
+    use super::*;
+
+    /// Water, sand and grass, where water never touches grass.
+    fn shores() -> GridModel<char> {
+        let mut sample = Grid::new(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                let tile = ['~', 's', 'g'][(mix(y * 32 + x) % 3) as usize];
+                sample.set(x as usize, y as usize, Some(tile));
+            }
+        }
+        let mut shores = sample.clone();
+        for y in 0..32 {
+            for x in 0..32 {
+                let wet = |dx: isize, dy: isize| {
+                    let (x, y) = (x as isize + dx, y as isize + dy);
+                    x >= 0 && y >= 0 && sample.get(x as usize, y as usize) == Some(&'~')
+                };
+                if sample.get(x, y) == Some(&'g')
+                    && (wet(-1, 0) || wet(1, 0) || wet(0, -1) || wet(0, 1))
+                {
+                    shores.set(x, y, Some('s'));
+                }
+            }
+        }
+        let mut m = GridModel::new(Neighborhood::VonNeumann);
+        m.train(&shores);
+        m
+    }
+
+    /// Lines that join up into boxes, turned and flipped every
+    /// way, with empty space around them.
+    fn tiles() -> GridModel<char> {
+        let boxes: Vec<Vec<char>> = [
+            "           ",
+            " ┏━━━┓     ",
+            " ┃   ┃     ",
+            " ┃   ┣━━━┓ ",
+            " ┃   ┃   ┃ ",
+            " ┃   ┣━━━┛ ",
+            " ┃   ┃     ",
+            " ┗━━━┛     ",
+            "           ",
+        ]
+        .iter()
+        .map(|row| row.chars().collect())
+        .collect();
+        let turn = |c: &char| {
+            let (from, to) = ("┏┓┛┗━┃┣┳┫┻", "┓┛┗┏┃━┳┫┻┣");
+            match from.chars().position(|f| f == *c) {
+                Some(i) => to.chars().nth(i).unwrap(),
+                None => *c,
+            }
+        };
+        let flip = |c: &char| {
+            let (from, to) = ("┏┓┗┛┣┫", "┓┏┛┗┫┣");
+            match from.chars().position(|f| f == *c) {
+                Some(i) => to.chars().nth(i).unwrap(),
+                None => *c,
+            }
+        };
+        let mut m = GridModel::new(Neighborhood::VonNeumann).border(' ');
+        let symmetry = Symmetry::new().rotations(turn).reflections(flip);
+        m.train_symmetric(&Grid::from_rows(&boxes), &symmetry);
+        m
+    }
+
+    /// Generates the chunks from 'from' to 'to', both included,
+    /// and stitches them into one map.
+    fn stitch(world: &ChunkedWorld<char>, from: (i64, i64), to: (i64, i64)) -> Grid<char> {
+        let (w, h) = world.chunk_size();
+        let across = (to.0 - from.0 + 1) as usize;
+        let down = (to.1 - from.1 + 1) as usize;
+        let mut map = Grid::new(across * w, down * h);
+        for cy in from.1..=to.1 {
+            for cx in from.0..=to.0 {
+                let chunk = world.chunk(cx, cy).unwrap();
+                assert!(chunk.is_complete());
+                for y in 0..h {
+                    for x in 0..w {
+                        let mx = (cx - from.0) as usize * w + x;
+                        let my = (cy - from.1) as usize * h + y;
+                        map.set(mx, my, chunk.get(x, y).cloned());
+                    }
+                }
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn chunks_meet_at_seams() {
+        let world = ChunkedWorld::new(shores(), 6, 5, 42);
+        assert_eq!(world.chunk_size(), (6, 5));
+
+        // Stitch the 3x3 chunks around the origin together.
+        let map = stitch(&world, (-1, -1), (1, 1));
+        for y in 0..15 {
+            for x in 0..18 {
+                let here = map.get(x, y).unwrap();
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if let Some(there) = map.get(nx, ny) {
+                        let mut pair = [*here, *there];
+                        pair.sort_unstable();
+                        assert_ne!(pair, ['g', '~'], "water meets grass at ({}, {})", x, y);
+                    }
+                }
+            }
+        }
+
+        // Chunks don't depend on what was generated before.
+        let again = ChunkedWorld::new(shores(), 6, 5, 42);
+        assert_eq!(again.chunk(1, 1).unwrap(), world.chunk(1, 1).unwrap());
+        let other = ChunkedWorld::new(shores(), 6, 5, 43);
+        assert_ne!(other.chunk(0, 0).unwrap(), world.chunk(0, 0).unwrap());
+    }
+
+    #[test]
+    fn tile_map_chunks_fit() {
+        let m = tiles();
+        for seed in 0..4 {
+            let world = ChunkedWorld::new(m.clone(), 8, 8, seed);
+            let map = stitch(&world, (-2, -2), (1, 1));
+            // Cells on the edge of the map see its border, rather
+            // than the chunks beyond.
+            let inside = |(x, y): &(usize, usize)| *x > 0 && *y > 0 && *x < 31 && *y < 31;
+            let conflicts: Vec<_> = m.conflicts(&map).into_iter().filter(inside).collect();
+            assert_eq!(conflicts, vec![], "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn untrained_world() {
+        let world = ChunkedWorld::new(GridModel::<char>::new(Neighborhood::Moore), 5, 5, 0);
+        assert_eq!(world.chunk(3, -2), Err(GridError::Unsatisfiable));
+        let world = world.options(GenerateOptions::new());
+        assert_eq!(world.chunk(3, -2), Err(GridError::Contradiction((1, 2))));
+    }
+}